@@ -1,21 +1,25 @@
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use crate::{
-    Located, PathExt as _, PathId,
+    LoadRestrictor, Located, PathExt as _, PathId,
     generator::{ConfigMapGenerator, Generator as _, SecretGenerator},
     load_component, load_kustomization,
-    manifest::{Kustomization, Manifest, Symbol},
-    plugin::FunctionPlugin,
+    manifest::{Kustomization, Manifest, Str, Symbol},
+    plugin::{ExecOptions, FunctionPlugin},
     reslist::ResourceList,
     resmap::ResourceMap,
     resource::{RefSpecs, Resource},
     transform::{
-        AnnotationTransformer, CleanupTransformer, ImageTagTransformer, LabelTransformer,
-        NameTransformer, NamespaceTransformer, PatchTransformer, Rename, RenameTransformer,
-        ReplicaTransformer, Transformer,
+        AnnotationTransformer, ChecksumTransformer, CleanupTransformer, ImageLockTransformer,
+        ImageRegistryTransformer, ImageTagTransformer, LabelTransformer, NameTransformer,
+        NamespaceTransformer, PatchTransformer, Rename, RenameTransformer, ReplicaTransformer,
+        Transformer,
     },
 };
-use anyhow::{Context, bail};
+use anyhow::{Context, bail, ensure};
 use compact_str::format_compact;
 use either::Either;
 use futures_util::future;
@@ -24,17 +28,83 @@ use tokio::sync::Mutex;
 
 const KUSTOMIZE_FUNCTION_ANNOTATION: &str = "config.kubernetes.io/function";
 
+/// Options that control a build, independent of the kustomization being built.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    pub load_restrictor: LoadRestrictor,
+    /// An image lock applied to the output of the build, in addition to any `imageLock`s
+    /// declared by kustomizations.
+    pub image_lock: Option<PathBuf>,
+    /// Don't apply any image lock, so images keep their tags, e.g. to list the images to lock.
+    pub skip_image_locks: bool,
+    /// Fail on images that aren't pinned to a digest if any image lock is applied, and on
+    /// patches, replicas, images and label field specs that match no resources.
+    pub strict: bool,
+    /// Whether to decrypt SOPS-encrypted resources, overriding the `decrypt` field of
+    /// kustomizations. `Some(false)` keeps the encrypted form, e.g. to render without keys in CI.
+    pub decrypt: Option<bool>,
+    /// Age identities to decrypt SOPS-encrypted resources and secret sources with, in the format
+    /// of an age key file, instead of those from `SOPS_AGE_KEY`, `SOPS_AGE_KEY_FILE` or the
+    /// default key file.
+    pub sops_age_keys: Option<Str>,
+    /// Whether `execs` generator sources may run commands, and the timeout of all commands.
+    pub exec: ExecOptions,
+    /// Annotate pod templates with checksums of the ConfigMaps and Secrets they refer to, in
+    /// addition to builds of kustomizations that set `checksumAnnotations`.
+    pub checksum_annotations: bool,
+}
+
 #[derive(Debug, Default)]
 pub struct Builder {
-    resources_cache: Mutex<IndexMap<PathId, Box<[Resource]>>>,
+    options: BuildOptions,
+    // Keyed by the symlink-resolved path, so a file reachable through several symlinks is loaded once,
+    // and whether the resources are decrypted.
+    #[allow(clippy::type_complexity)]
+    resources_cache: Mutex<IndexMap<(PathId, bool), Box<[Resource]>>>,
+    image_locks_applied: AtomicBool,
 }
 
 impl Builder {
+    pub fn new(options: BuildOptions) -> Self {
+        Self {
+            options,
+            resources_cache: Default::default(),
+            image_locks_applied: Default::default(),
+        }
+    }
+
     pub async fn build_kust(
         &self,
         kustomization: &Located<Kustomization>,
     ) -> anyhow::Result<ResourceMap> {
-        let (resmap, _nested_renames) = self.build(Default::default(), kustomization).await?;
+        let (mut resmap, _nested_renames) =
+            self.build(Default::default(), kustomization, &[]).await?;
+
+        if let Some(path) = &self.options.image_lock
+            && !self.options.skip_image_locks
+        {
+            let path = PathId::make(path)
+                .with_context(|| format!("resolving image lock `{}`", path.pretty()))?;
+            ImageLockTransformer::load(path)?
+                .transform(&mut resmap)
+                .await?;
+        }
+
+        // Checked once on the output, as images may be pinned by any lock in the build.
+        if self.options.strict
+            && ((self.options.image_lock.is_some() && !self.options.skip_image_locks)
+                || self.image_locks_applied.load(Ordering::Relaxed))
+        {
+            ImageLockTransformer::ensure_pinned(&resmap)?;
+        }
+
+        // Before hash suffixes are added, so the annotation keys are stable across changes to the data.
+        let refspecs = RefSpecs::load_builtin();
+        if self.options.checksum_annotations || kustomization.checksum_annotations {
+            ChecksumTransformer::new(refspecs)
+                .transform(&mut resmap)
+                .await?;
+        }
 
         let mut out = ResourceMap::with_capacity(resmap.len());
         let mut renames = vec![];
@@ -50,7 +120,6 @@ impl Builder {
             }
         }
 
-        let refspecs = RefSpecs::load_builtin();
         RenameTransformer::new(refspecs, &renames)
             .transform(&mut out)
             .await?;
@@ -66,14 +135,24 @@ impl Builder {
         resmap: &mut ResourceMap,
         renames: &mut Vec<Rename>,
     ) -> anyhow::Result<()> {
+        let strict = self.options.strict || kustomization.strict;
+
         if !kustomization.labels.is_empty() {
             assert!(
                 kustomization.common_labels.is_empty(),
                 "commonLabels should be translated to labels"
             );
-            LabelTransformer::new(kustomization.labels.as_ref())
-                .transform(resmap)
-                .await?;
+            let mut transformer = LabelTransformer::new(kustomization.labels.as_ref());
+            transformer.transform(resmap).await?;
+            if strict {
+                ensure_matched(
+                    kustomization,
+                    transformer
+                        .unmatched_fields()
+                        .iter()
+                        .map(|(i, j)| format!("labels[{i}].fields[{j}]")),
+                )?;
+            }
         }
 
         if !kustomization.common_annotations.is_empty() {
@@ -83,27 +162,35 @@ impl Builder {
         }
 
         if let Some(namespace) = &kustomization.namespace {
-            for res in resmap.iter() {
-                if res.namespace() != Some(namespace) {
-                    renames.push(Rename::new_namespace(res.id().clone(), namespace.clone()));
-                }
-            }
-
-            NamespaceTransformer(namespace.clone())
-                .transform(resmap)
-                .await?;
+            let mut transformer = NamespaceTransformer::new(namespace.clone());
+            renames.extend(transformer.renames(resmap)?);
+            transformer.transform(resmap).await?;
         }
 
         if !kustomization.patches.is_empty() {
-            PatchTransformer::new(kustomization)
-                .transform(resmap)
-                .await?;
+            let mut transformer =
+                PatchTransformer::new(kustomization, self.options.load_restrictor);
+            transformer.transform(resmap).await?;
+            renames.extend(transformer.take_renames());
+            if strict {
+                ensure_matched(
+                    kustomization,
+                    transformer.unmatched().map(|i| format!("patches[{i}]")),
+                )?;
+            }
         }
 
         if !kustomization.replicas.is_empty() {
-            ReplicaTransformer::new(&kustomization.replicas)
-                .transform(resmap)
-                .await?;
+            let mut transformer = ReplicaTransformer::new(&kustomization.replicas);
+            transformer.transform(resmap).await?;
+            if strict {
+                ensure_matched(
+                    kustomization,
+                    transformer
+                        .unmatched()
+                        .map(|i| format!("replicas[{i}] (`{}`)", kustomization.replicas[i].name)),
+                )?;
+            }
         }
 
         match (&kustomization.name_prefix, &kustomization.name_suffix) {
@@ -122,23 +209,52 @@ impl Builder {
         };
 
         if !kustomization.images.is_empty() {
-            for image in &kustomization.images {
-                ImageTagTransformer::from(image.clone())
-                    .transform(resmap)
-                    .await
-                    .with_context(|| {
-                        format!(
-                            "transforming resources with image tag spec {:?} in `{}`",
-                            image,
-                            kustomization.path.pretty()
-                        )
-                    })?;
+            let mut unmatched = vec![];
+            for (i, image) in kustomization.images.iter().enumerate() {
+                let mut transformer = ImageTagTransformer::from(image.clone());
+                transformer.transform(resmap).await.with_context(|| {
+                    format!(
+                        "transforming resources with image tag spec {:?} in `{}`",
+                        image,
+                        kustomization.path.pretty()
+                    )
+                })?;
+
+                if !transformer.matched() {
+                    unmatched.push(format!("images[{i}] (`{}`)", image.name));
+                }
+            }
+
+            if strict {
+                ensure_matched(kustomization, unmatched)?;
             }
         }
 
+        if let Some(path) = &kustomization.image_lock
+            && !self.options.skip_image_locks
+        {
+            let path = self
+                .options
+                .load_restrictor
+                .resolve_file(&kustomization.parent_path, path)?;
+            self.image_locks_applied.store(true, Ordering::Relaxed);
+            ImageLockTransformer::load(path)?
+                .transform(resmap)
+                .await
+                .with_context(|| {
+                    format!(
+                        "pinning images with image lock in `{}`",
+                        kustomization.path.pretty()
+                    )
+                })?;
+        }
+
         for path in &kustomization.transformers {
-            let path = PathId::make(kustomization.parent_path.join(path))?;
-            self.apply_transformer(path, resmap).await?;
+            let path = self
+                .options
+                .load_restrictor
+                .resolve_file(&kustomization.parent_path, path)?;
+            self.apply_transformer(path, resmap, renames).await?;
         }
 
         Ok(())
@@ -151,16 +267,35 @@ impl Builder {
         &self,
         resmap: ResourceMap,
         kustomization: &Located<Manifest<A, K>>,
+        ancestors: &[PathId],
     ) -> anyhow::Result<(ResourceMap, Vec<Rename>)> {
-        let mut resmap = self.build_kustomization_base(resmap, kustomization).await?;
+        if ancestors.contains(&kustomization.path) {
+            let cycle = ancestors
+                .iter()
+                .skip_while(|&&path| path != kustomization.path)
+                .chain([&kustomization.path])
+                .map(|path| path.pretty().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            bail!("cycle detected between kustomizations: {cycle}");
+        }
+
+        let ancestors = &[ancestors, &[kustomization.path]].concat();
+
+        let mut resmap = self
+            .build_kustomization_base(resmap, kustomization, ancestors)
+            .await?;
         let mut renames = vec![];
 
         self.apply_generators(kustomization, &mut resmap).await?;
 
         for component in &kustomization.components {
-            let component = load_component(kustomization.parent_path.join(component))
-                .with_context(|| format!("loading component `{}`", component.pretty()))?;
-            let (new_resmap, component_renames) = self.build(resmap, &component).await?;
+            let component = load_component(
+                kustomization.parent_path.join(component),
+                self.options.load_restrictor,
+            )
+            .with_context(|| format!("loading component `{}`", component.pretty()))?;
+            let (new_resmap, component_renames) = self.build(resmap, &component, ancestors).await?;
             resmap = new_resmap;
             renames.extend(component_renames);
         }
@@ -209,6 +344,8 @@ impl Builder {
                 kustomization.config_map_generators.as_ref(),
                 &kustomization.generator_options,
             )
+            .with_load_restrictor(self.options.load_restrictor)
+            .with_exec_options(self.options.exec)
             .generate(&kustomization.parent_path, &ResourceList::new([]))
             .await?;
 
@@ -225,6 +362,9 @@ impl Builder {
                 &kustomization.secret_generators,
                 &kustomization.generator_options,
             )
+            .with_load_restrictor(self.options.load_restrictor)
+            .with_exec_options(self.options.exec)
+            .with_sops_age_keys(self.options.sops_age_keys.as_deref())
             .generate(&kustomization.parent_path, &ResourceList::new([]))
             .await?;
 
@@ -243,10 +383,11 @@ impl Builder {
         &self,
         mut resmap: ResourceMap,
         kustomization: &Located<Manifest<A, K>>,
+        ancestors: &[PathId],
     ) -> anyhow::Result<ResourceMap> {
         let resources =
             future::try_join_all(kustomization.resources.iter().map(|path| async move {
-                let built = self.build_resource(kustomization, path).await?;
+                let built = self.build_resource(kustomization, path, ancestors).await?;
                 anyhow::Ok((path, built))
             }))
             .await?;
@@ -290,8 +431,12 @@ impl Builder {
         &self,
         kustomization: &Located<Manifest<A, K>>,
         path: &Path,
+        ancestors: &[PathId],
     ) -> anyhow::Result<Either<Box<[Resource]>, (ResourceMap, Vec<Rename>)>> {
-        let path = PathId::make(kustomization.parent_path.join(path)).with_context(|| {
+        // `declared` is the path as written in the kustomization and is used for error messages,
+        // `resolved` has all symlinks resolved and is used for caching and load restrictions.
+        let declared = kustomization.parent_path.join(path);
+        let resolved = PathId::make(&declared).with_context(|| {
             format!(
                 "resolving resource path `{}` in `{}`",
                 path.pretty(),
@@ -299,17 +444,27 @@ impl Builder {
             )
         })?;
 
-        let metadata = std::fs::metadata(path)
-            .with_context(|| format!("reading metadata for resource {}", path.pretty()))?;
+        let metadata = std::fs::metadata(resolved)
+            .with_context(|| format!("reading metadata for resource {}", declared.pretty()))?;
+
+        if metadata.is_file() {
+            self.options
+                .load_restrictor
+                .check(&kustomization.parent_path, &declared, resolved)?;
 
-        if metadata.is_symlink() {
-            bail!("symlinks are not implemented: {}", path.pretty());
-        } else if metadata.is_file() {
-            let res = match self.resources_cache.lock().await.entry(path) {
+            let decrypt = self.options.decrypt.unwrap_or(kustomization.decrypt);
+            let res = match self.resources_cache.lock().await.entry((resolved, decrypt)) {
                 Entry::Occupied(entry) => entry.into_mut(),
                 Entry::Vacant(entry) => {
-                    let res = Resource::load_many(path)
-                        .with_context(|| format!("load resource {}", path.pretty()))?;
+                    let res = if decrypt {
+                        Resource::load_many_decrypted(
+                            resolved,
+                            self.options.sops_age_keys.as_deref(),
+                        )
+                    } else {
+                        Resource::load_many(resolved)
+                    }
+                    .with_context(|| format!("load resource {}", declared.pretty()))?;
                     entry.insert(res)
                 }
             }
@@ -317,13 +472,15 @@ impl Builder {
 
             Ok(Either::Left(res))
         } else {
-            let kustomization = load_kustomization(path)
-                .with_context(|| format!("load kustomization resource {}", path.pretty()))?;
+            let kustomization = load_kustomization(resolved, self.options.load_restrictor)
+                .with_context(|| format!("load kustomization resource {}", declared.pretty()))?;
 
             let (base, renames) = self
-                .build(Default::default(), &kustomization)
+                .build(Default::default(), &kustomization, ancestors)
                 .await
-                .with_context(|| format!("building kustomization resource {}", path.pretty()))?;
+                .with_context(|| {
+                    format!("building kustomization resource {}", declared.pretty())
+                })?;
 
             Ok(Either::Right((base, renames)))
         }
@@ -333,6 +490,7 @@ impl Builder {
         &self,
         path: PathId,
         resmap: &mut ResourceMap,
+        renames: &mut Vec<Rename>,
     ) -> anyhow::Result<()> {
         let transformer_spec = Resource::load_one(path)
             .with_context(|| format!("loading transformer spec from {}", path.pretty()))?;
@@ -342,6 +500,7 @@ impl Builder {
         {
             let function_spec = annotations.function_spec()?.unwrap();
             FunctionPlugin::new(function_spec)
+                .with_exec_options(self.options.exec)
                 .transform(resmap)
                 .await
                 .with_context(|| {
@@ -360,6 +519,26 @@ impl Builder {
                     .transform(resmap)
                     .await?
                 }
+                "ImageRegistryTransformer" => {
+                    json::from_value::<ImageRegistryTransformer>(json::Value::Object(
+                        transformer_spec.root().clone(),
+                    ))
+                    .with_context(|| {
+                        format!("parsing ImageRegistryTransformer at `{}`", path.pretty())
+                    })?
+                    .transform(resmap)
+                    .await?
+                }
+                "NamespaceTransformer" => {
+                    let mut transformer = json::from_value::<NamespaceTransformer>(
+                        json::Value::Object(transformer_spec.root().clone()),
+                    )
+                    .with_context(|| {
+                        format!("parsing NamespaceTransformer at `{}`", path.pretty())
+                    })?;
+                    renames.extend(transformer.renames(resmap)?);
+                    transformer.transform(resmap).await?
+                }
                 "LabelTransformer" => {
                     json::from_value::<LabelTransformer<'_>>(json::Value::Object(
                         transformer_spec.root().clone(),
@@ -390,7 +569,10 @@ impl Builder {
         kustomization: &Located<Manifest<A, K>>,
         path: &Path,
     ) -> anyhow::Result<ResourceList> {
-        let path = PathId::make(kustomization.parent_path.join(path))?;
+        let path = self
+            .options
+            .load_restrictor
+            .resolve_file(&kustomization.parent_path, path)?;
         let workdir = path.parent().unwrap();
         let generator_spec = Resource::load_one(path)
             .with_context(|| format!("loading generator spec from {}", path.pretty()))?;
@@ -408,6 +590,7 @@ impl Builder {
                 })?
                 .unwrap();
             let generated = FunctionPlugin::new(function_spec)
+                .with_exec_options(self.options.exec)
                 .generate(workdir, &ResourceList::new([generator_spec]))
                 .await
                 .with_context(|| {
@@ -424,9 +607,9 @@ impl Builder {
                     let mut generator = json::from_value::<ConfigMapGenerator<'_>>(
                         json::Value::Object(generator_spec.root().clone()),
                     )
-                    .with_context(|| {
-                        format!("parsing ConfigMapGenerator at `{}`", path.pretty())
-                    })?;
+                    .with_context(|| format!("parsing ConfigMapGenerator at `{}`", path.pretty()))?
+                    .with_load_restrictor(self.options.load_restrictor)
+                    .with_exec_options(self.options.exec);
                     generator.set_options(&kustomization.generator_options);
 
                     let generated = generator
@@ -455,3 +638,65 @@ impl Builder {
         }
     }
 }
+
+/// Fails with the entries of the kustomization that did not match any resources in strict mode.
+fn ensure_matched<A, K>(
+    kustomization: &Located<Manifest<A, K>>,
+    unmatched: impl IntoIterator<Item = String>,
+) -> anyhow::Result<()> {
+    let unmatched = unmatched.into_iter().collect::<Vec<_>>();
+    ensure!(
+        unmatched.is_empty(),
+        "{} in `{}` did not match any resources",
+        unmatched.join(", "),
+        kustomization.path.pretty()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn skip_image_locks() {
+        let options = BuildOptions {
+            image_lock: Some("tests/kustomizer/testdata/image-lock/images.lock.yaml".into()),
+            skip_image_locks: true,
+            strict: true,
+            ..Default::default()
+        };
+        let resmap = crate::build_with_options("tests/kustomizer/testdata/image-lock", options)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            resmap.images().unwrap(),
+            [
+                "app:1.4.2",
+                "nginx",
+                "registry.local:5000/sidecar:v2",
+                "redis:7@sha256:2222222222222222222222222222222222222222222222222222222222222222",
+                "busybox:1.36",
+            ]
+        );
+    }
+    #[tokio::test]
+    async fn sops_age_keys() {
+        let dir = Path::new("tests/kustomizer/testdata/sops-decrypt");
+        let options = BuildOptions {
+            sops_age_keys: Some(
+                std::fs::read_to_string(dir.join("keys.txt"))
+                    .unwrap()
+                    .into(),
+            ),
+            ..Default::default()
+        };
+        let resmap = crate::build_with_options(dir, options).await.unwrap();
+
+        assert_eq!(
+            resmap.to_string(),
+            std::fs::read_to_string(dir.join("output.yaml")).unwrap()
+        );
+    }
+}
@@ -0,0 +1,433 @@
+mod create;
+mod document;
+mod fix;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::{Context, bail, ensure};
+use indexmap::IndexMap;
+use regex::Regex;
+use serde::{Serialize, de::DeserializeOwned};
+
+pub use self::create::CreateOptions;
+
+use self::document::Document;
+use crate::{
+    PathExt as _,
+    image::ImageRef,
+    manifest::{ImageTag, Label, Manifest, Patch, Str, Symbol, apiversion, kind},
+    yaml,
+};
+
+/// A kustomization or component file that is edited in place. Only the fields that are changed
+/// are rewritten, the comments, key order and formatting of the rest of the file are preserved.
+#[derive(Debug)]
+pub struct ManifestFile<A, K> {
+    path: PathBuf,
+    document: Document,
+    manifest: Manifest<A, K>,
+}
+
+pub type KustomizationFile = ManifestFile<apiversion::V1Beta1, kind::Kustomize>;
+pub type ComponentFile = ManifestFile<apiversion::V1Alpha1, kind::Component>;
+
+const FILE_NAMES: [&str; 2] = ["kustomization.yaml", "kustomization.yml"];
+
+/// The `kustomization.yaml` or `kustomization.yml` in `dir`.
+fn find(dir: &Path) -> anyhow::Result<PathBuf> {
+    let dir = &std::path::absolute(dir)?;
+    match FILE_NAMES
+        .map(|name| dir.join(name))
+        .into_iter()
+        .filter(|path| path.exists())
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [path] => Ok(path.clone()),
+        [] => bail!(
+            "no kustomization.yaml or kustomization.yml in {}",
+            dir.pretty()
+        ),
+        _ => bail!(
+            "both kustomization.yaml and kustomization.yml exist in directory: {}",
+            dir.pretty()
+        ),
+    }
+}
+
+/// Whether the kustomization file in `dir` is a `Component`.
+pub fn is_component(dir: impl AsRef<Path>) -> anyhow::Result<bool> {
+    let path = find(dir.as_ref())?;
+    let source =
+        std::fs::read_to_string(&path).with_context(|| format!("reading {}", path.pretty()))?;
+    let manifest = yaml::from_str::<json::Value>(&source)
+        .with_context(|| format!("parsing {}", path.pretty()))?;
+    Ok(manifest["kind"] == kind::Component::VALUE)
+}
+
+impl<A, K> ManifestFile<A, K>
+where
+    A: Symbol + Default + Serialize + DeserializeOwned,
+    K: Symbol + Default + Serialize + DeserializeOwned,
+{
+    /// Loads the `kustomization.yaml` or `kustomization.yml` in `dir`.
+    pub fn load(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = find(dir.as_ref())?;
+        let source =
+            std::fs::read_to_string(&path).with_context(|| format!("reading {}", path.pretty()))?;
+        let manifest = yaml::from_str::<Manifest<A, K>>(&source)
+            .with_context(|| format!("parsing {}", path.pretty()))?;
+
+        Ok(Self {
+            path,
+            document: Document::new(source),
+            manifest,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn manifest(&self) -> &Manifest<A, K> {
+        &self.manifest
+    }
+
+    /// The edited source of the file.
+    pub fn as_str(&self) -> &str {
+        self.document.as_str()
+    }
+
+    /// Writes the edited file back to disk.
+    pub fn save(&self) -> anyhow::Result<()> {
+        // Guard against edits that would change any other field.
+        let reparsed = yaml::from_str::<Manifest<A, K>>(self.as_str())
+            .with_context(|| format!("edit of {} produced invalid yaml", self.path.pretty()))?;
+        ensure!(
+            json::to_value(&reparsed)? == json::to_value(&self.manifest)?,
+            "edit of {} would change fields other than the edited ones",
+            self.path.pretty()
+        );
+
+        std::fs::write(&self.path, self.as_str())
+            .with_context(|| format!("writing {}", self.path.pretty()))
+    }
+
+    pub fn set_namespace(&mut self, namespace: &str) -> anyhow::Result<()> {
+        static DNS_LABEL: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$").unwrap());
+
+        ensure!(
+            namespace.len() <= 63 && DNS_LABEL.is_match(namespace),
+            "invalid namespace `{namespace}`: must be a DNS label of at most 63 lowercase alphanumeric characters or `-`, starting and ending with an alphanumeric character"
+        );
+        self.manifest.namespace = Some(namespace.into());
+        self.document.set_field("namespace", &namespace)
+    }
+
+    pub fn set_name_prefix(&mut self, prefix: &str) -> anyhow::Result<()> {
+        self.manifest.name_prefix = prefix.into();
+        self.document.set_field("namePrefix", &prefix)
+    }
+
+    /// Sets an image override as `kustomize edit set image` does, from `name=new-image`, or
+    /// `image` to override the tag or digest of an image without renaming it. The new image may
+    /// omit the tag and digest to keep those of an existing override.
+    pub fn set_image(&mut self, spec: &str) -> anyhow::Result<()> {
+        let (name, image, rename) = match spec.split_once('=') {
+            Some((name, image)) => {
+                ensure!(
+                    !name.is_empty(),
+                    "missing image name before `=` in `{spec}`"
+                );
+                (Str::from(name), image.parse::<ImageRef>()?, true)
+            }
+            None => {
+                let image = spec.parse::<ImageRef>()?;
+                (image.name(), image, false)
+            }
+        };
+
+        let mut images = self.manifest.images.to_vec();
+        let index = images.iter().position(|tag| tag.name == name);
+        let tag = match index {
+            Some(index) => &mut images[index],
+            None => {
+                images.push(ImageTag {
+                    name: name.clone(),
+                    new_name: Default::default(),
+                    new_tag: Default::default(),
+                    digest: Default::default(),
+                    tag_suffix: Default::default(),
+                });
+                images.last_mut().unwrap()
+            }
+        };
+
+        // Renaming an image to itself only sets the tag or digest, as `newName` would be redundant.
+        if rename {
+            tag.new_name = match image.name() {
+                new_name if new_name == name => Default::default(),
+                new_name => new_name,
+            };
+        }
+
+        if image.tag.is_some() || image.digest.is_some() {
+            tag.new_tag = image.tag.unwrap_or_default();
+            tag.digest = image.digest.unwrap_or_default();
+            tag.tag_suffix = Default::default();
+        }
+
+        match index {
+            Some(index) => self.document.replace_item("images", &images, index)?,
+            None => self.document.push_item("images", &images)?,
+        }
+
+        self.manifest.images = images.into_boxed_slice();
+        Ok(())
+    }
+
+    /// Adds resources, expanding glob patterns relative to the kustomization directory.
+    /// Returns the resources that were added, skipping those that are already listed.
+    pub fn add_resources(&mut self, resources: &[String]) -> anyhow::Result<Vec<PathBuf>> {
+        let dir = self.path.parent().unwrap();
+        let mut paths = vec![];
+        for resource in resources {
+            if !resource.contains(['*', '?', '[']) {
+                paths.push(PathBuf::from(resource));
+                continue;
+            }
+
+            let dir_pattern = dir
+                .to_str()
+                .with_context(|| format!("non-utf8 path {}", dir.pretty()))?;
+            let pattern = format!("{}/{resource}", glob::Pattern::escape(dir_pattern));
+            let mut matches = glob::glob(&pattern)
+                .with_context(|| format!("invalid resource pattern `{resource}`"))?
+                .map(|path| Ok(path?.strip_prefix(dir)?.to_path_buf()))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            ensure!(
+                !matches.is_empty(),
+                "resource pattern `{resource}` matches no files"
+            );
+            matches.sort();
+            paths.append(&mut matches);
+        }
+
+        self.add_resource_paths(paths)
+    }
+
+    /// Adds the paths as resources without expanding glob patterns, skipping those that are
+    /// already listed.
+    pub(crate) fn add_resource_paths(
+        &mut self,
+        paths: Vec<PathBuf>,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let mut all = self.manifest.resources.to_vec();
+        let mut added = vec![];
+        for path in paths {
+            if all.contains(&path) {
+                continue;
+            }
+
+            all.push(path.clone());
+            self.document.push_item("resources", &all)?;
+            added.push(path);
+        }
+
+        self.manifest.resources = all.into_boxed_slice();
+        Ok(added)
+    }
+
+    /// Adds `pairs` to the `labels` entry with the same options, or a new entry if there is none.
+    /// Changing the value of a label that is already set requires `force`.
+    pub fn add_labels(
+        &mut self,
+        pairs: IndexMap<Str, Str>,
+        include_selectors: bool,
+        include_templates: bool,
+        force: bool,
+    ) -> anyhow::Result<()> {
+        let mut labels = self.manifest.labels.to_vec();
+        let index = labels.iter().position(|label| {
+            label.include_selectors == include_selectors
+                && label.include_templates == include_templates
+                && label.fields.is_empty()
+        });
+
+        match index {
+            Some(index) => {
+                let existing = &mut labels[index].pairs;
+                for (key, value) in pairs {
+                    if let Some(old) = existing.get(&key)
+                        && *old != value
+                        && !force
+                    {
+                        bail!(
+                            "label `{key}` is already set to `{old}`, use --force to overwrite it"
+                        );
+                    }
+                    existing.insert(key, value);
+                }
+                self.document.replace_item("labels", &labels, index)?;
+            }
+            None => {
+                labels.push(Label {
+                    pairs,
+                    include_selectors,
+                    include_templates,
+                    fields: Default::default(),
+                });
+                self.document.push_item("labels", &labels)?;
+            }
+        }
+
+        self.manifest.labels = labels.into_boxed_slice();
+        Ok(())
+    }
+
+    /// Adds a patch to `patches`, returning `false` if an identical patch is already listed.
+    pub fn add_patch(&mut self, patch: Patch) -> anyhow::Result<bool> {
+        let value = json::to_value(&patch)?;
+        for existing in &self.manifest.patches {
+            if json::to_value(existing)? == value {
+                return Ok(false);
+            }
+        }
+
+        let mut patches = self.manifest.patches.to_vec();
+        patches.push(patch);
+        self.document.push_item("patches", &patches)?;
+        self.manifest.patches = patches.into_boxed_slice();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    const SOURCE: &str = "\
+apiVersion: kustomize.config.k8s.io/v1beta1
+kind: Kustomization
+# Deployed to the staging cluster.
+namespace: staging
+
+resources:
+  - deployment.yaml # the app
+
+images:
+  # Bumped by the release bot.
+  - name: ghcr.io/org/app
+    newTag: v1.0.0
+";
+
+    fn edit(f: impl FnOnce(&mut KustomizationFile) -> anyhow::Result<()>) -> String {
+        let dir = TempDir::new("edit");
+        std::fs::write(dir.join("kustomization.yml"), SOURCE).unwrap();
+        std::fs::write(dir.join("service.yaml"), "").unwrap();
+
+        let mut file = KustomizationFile::load(&dir).unwrap();
+        f(&mut file).unwrap();
+        file.save().unwrap();
+        std::fs::read_to_string(dir.join("kustomization.yml")).unwrap()
+    }
+
+    #[test]
+    fn set_image() {
+        let edited = edit(|file| {
+            file.set_image("ghcr.io/org/app:v1.1.0")?;
+            file.set_image("nginx=registry.local:5000/nginx@sha256:abc")?;
+            file.set_image("redis=redis:alpine")
+        });
+        assert_eq!(
+            edited,
+            SOURCE.replace("newTag: v1.0.0", "newTag: v1.1.0")
+                + "  - name: nginx\n    newName: registry.local:5000/nginx\n    digest: sha256:abc\n"
+                + "  - name: redis\n    newTag: alpine\n"
+        );
+    }
+
+    #[test]
+    fn set_namespace() {
+        let edited = edit(|file| {
+            for invalid in ["", "Prod", "prod_1", "-prod", "prod.eu"] {
+                assert!(
+                    file.set_namespace(invalid).is_err(),
+                    "expected `{invalid}` to be rejected"
+                );
+            }
+            file.set_namespace("prod")
+        });
+        assert_eq!(
+            edited,
+            SOURCE.replace("namespace: staging", "namespace: prod")
+        );
+    }
+
+    #[test]
+    fn add_resources() {
+        let edited = edit(|file| {
+            let added = file.add_resources(&["deployment.yaml".into(), "*.yaml".into()])?;
+            assert_eq!(added, [PathBuf::from("service.yaml")]);
+            Ok(())
+        });
+        assert_eq!(
+            edited,
+            SOURCE.replace(
+                "  - deployment.yaml # the app\n",
+                "  - deployment.yaml # the app\n  - service.yaml\n"
+            )
+        );
+    }
+
+    #[test]
+    fn add_labels() {
+        let edited = edit(|file| {
+            file.add_labels([("team".into(), "web".into())].into(), false, true, false)?;
+            file.add_labels(
+                [("tier".into(), "frontend".into())].into(),
+                false,
+                true,
+                false,
+            )?;
+            let err = file
+                .add_labels([("team".into(), "api".into())].into(), false, true, false)
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "label `team` is already set to `web`, use --force to overwrite it"
+            );
+            Ok(())
+        });
+        assert_eq!(
+            edited,
+            format!(
+                "{SOURCE}labels:\n  - pairs:\n      team: web\n      tier: frontend\n    includeTemplates: true\n"
+            )
+        );
+    }
+
+    #[test]
+    fn add_patch() {
+        let edited = edit(|file| {
+            let patch = json::from_value::<Patch>(json::json!({
+                "path": "patch.yaml",
+                "target": { "kind": "Deployment", "labelSelector": "app=web" },
+            }))?;
+            assert!(file.add_patch(patch.clone())?);
+            assert!(!file.add_patch(patch)?);
+            Ok(())
+        });
+        assert_eq!(
+            edited,
+            format!(
+                "{SOURCE}patches:\n  - path: patch.yaml\n    target:\n      labelSelector: app=web\n      kind: Deployment\n"
+            )
+        );
+    }
+}
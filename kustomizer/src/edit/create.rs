@@ -0,0 +1,198 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, ensure};
+
+use super::{Document, FILE_NAMES, KustomizationFile};
+use crate::{
+    PathExt as _,
+    manifest::{Kustomization, Str, TypeMeta},
+    resource::Resource,
+    yaml,
+};
+
+/// Options for [`KustomizationFile::create`], mirroring `kustomize create`.
+#[derive(Debug, Clone, Default)]
+pub struct CreateOptions {
+    /// Add the Kubernetes resources found in the directory.
+    pub autodetect: bool,
+    /// Search subdirectories when autodetecting, directories with a kustomization are added as
+    /// a whole.
+    pub recursive: bool,
+    /// Resources to add, glob patterns are expanded as by `edit add resource`.
+    pub resources: Vec<String>,
+    pub namespace: Option<Str>,
+    pub name_prefix: Option<Str>,
+}
+
+impl KustomizationFile {
+    /// Creates a new `kustomization.yaml` in `dir`, which is written by [`Self::save`].
+    pub fn create(dir: impl AsRef<Path>, options: CreateOptions) -> anyhow::Result<Self> {
+        let dir = std::path::absolute(dir.as_ref())?;
+        ensure!(
+            !has_kustomization(&dir),
+            "a kustomization file already exists in {}",
+            dir.pretty()
+        );
+
+        let type_meta = TypeMeta::default();
+        let source = yaml::to_string(&type_meta)?;
+        let mut file = Self {
+            path: dir.join("kustomization.yaml"),
+            document: Document::new(source),
+            manifest: Kustomization {
+                type_meta,
+                ..yaml::from_str("{}")?
+            },
+        };
+
+        file.add_resources(&options.resources)?;
+        if options.autodetect {
+            // Detected paths are literal, names such as `cm[1].yaml` must not be expanded.
+            let detected = detect_resources(&dir, &dir, options.recursive)?;
+            file.add_resource_paths(detected)?;
+        }
+
+        if let Some(namespace) = &options.namespace {
+            file.set_namespace(namespace)?;
+        }
+
+        if let Some(prefix) = &options.name_prefix {
+            file.set_name_prefix(prefix)?;
+        }
+
+        Ok(file)
+    }
+}
+
+fn has_kustomization(dir: &Path) -> bool {
+    FILE_NAMES.iter().any(|name| dir.join(name).exists())
+}
+
+/// The files in `dir` that contain Kubernetes resources, relative to `root`. YAML that isn't a
+/// resource, such as values files, is skipped.
+fn detect_resources(root: &Path, dir: &Path, recursive: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("reading directory {}", dir.pretty()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    entries.sort();
+
+    let mut resources = vec![];
+    for path in entries {
+        let relative = path.strip_prefix(root)?;
+        if path.is_dir() {
+            if !recursive {
+                continue;
+            }
+
+            if has_kustomization(&path) {
+                resources.push(relative.to_path_buf());
+            } else {
+                resources.extend(detect_resources(root, &path, recursive)?);
+            }
+            continue;
+        }
+
+        let is_manifest = path
+            .extension()
+            .is_some_and(|ext| ext == "yaml" || ext == "yml" || ext == "json");
+        let is_kustomization = path
+            .file_name()
+            .is_some_and(|name| FILE_NAMES.iter().any(|file_name| name == *file_name));
+        if !is_manifest || is_kustomization {
+            continue;
+        }
+
+        match Resource::load_many(&path) {
+            Ok(loaded) if !loaded.is_empty() => resources.push(relative.to_path_buf()),
+            Ok(_) => {}
+            Err(err) => tracing::debug!(path = %path.display(), ?err, "skipping non-resource file"),
+        }
+    }
+
+    Ok(resources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn create() {
+        let dir = TempDir::new("create");
+        let resource = "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: cfg\n";
+        for (path, content) in [
+            ("b.yaml", resource),
+            ("cm[1].yaml", resource),
+            (
+                "a.json",
+                r#"{"apiVersion": "v1", "kind": "Secret", "metadata": {"name": "s"}}"#,
+            ),
+            ("values.yaml", "replicas: 3\n"),
+            ("notes.txt", resource),
+            ("nested/c.yml", resource),
+            ("nested/base/kustomization.yaml", "resources: []\n"),
+            ("nested/base/d.yaml", resource),
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let options = CreateOptions {
+            autodetect: true,
+            namespace: Some("apps".into()),
+            name_prefix: Some("dev-".into()),
+            ..Default::default()
+        };
+        let flat = KustomizationFile::create(&dir, options.clone()).unwrap();
+        let recursive = KustomizationFile::create(
+            &dir,
+            CreateOptions {
+                recursive: true,
+                resources: vec!["https://example.com/remote.yaml".into()],
+                ..options
+            },
+        )
+        .unwrap();
+        recursive.save().unwrap();
+        let exists = KustomizationFile::create(&dir, Default::default()).unwrap_err();
+
+        assert_eq!(
+            flat.as_str(),
+            "\
+apiVersion: kustomize.config.k8s.io/v1beta1
+kind: Kustomization
+resources:
+  - a.json
+  - b.yaml
+  - \"cm[1].yaml\"
+namespace: apps
+namePrefix: dev-
+"
+        );
+        assert_eq!(
+            recursive.as_str(),
+            "\
+apiVersion: kustomize.config.k8s.io/v1beta1
+kind: Kustomization
+resources:
+  - https://example.com/remote.yaml
+  - a.json
+  - b.yaml
+  - \"cm[1].yaml\"
+  - nested/base
+  - nested/c.yml
+namespace: apps
+namePrefix: dev-
+"
+        );
+        assert!(
+            exists
+                .to_string()
+                .starts_with("a kustomization file already exists in"),
+            "{exists}"
+        );
+    }
+}
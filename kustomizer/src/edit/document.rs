@@ -0,0 +1,438 @@
+use std::ops::Range;
+
+use indexmap::IndexMap;
+use json::Value;
+use serde::{Serialize, Serializer};
+
+use crate::yaml;
+
+/// The source of a YAML mapping that is edited by replacing the text of individual top-level
+/// fields and block sequence items, so the rest of the source is left untouched.
+#[derive(Debug, Clone)]
+pub(super) struct Document {
+    source: String,
+}
+
+/// The byte ranges of a top-level field.
+#[derive(Debug)]
+struct Field {
+    /// From the start of the key to the end of the last line of the value, excluding trailing
+    /// blank lines and unindented comments, which belong to the next field.
+    span: Range<usize>,
+    /// The items of the value if it's a block sequence, along with their indentation.
+    items: Option<(usize, Vec<Range<usize>>)>,
+}
+
+impl Document {
+    pub fn new(source: String) -> Self {
+        Self { source }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Sets the top-level `key` to `value`, replacing the existing field or appending it.
+    pub fn set_field<T: Serialize>(&mut self, key: &str, value: &T) -> anyhow::Result<()> {
+        let text = to_yaml(&IndexMap::from([(key, value)]))?;
+        match self.field(key) {
+            Some(field) => self.source.replace_range(field.span, &text),
+            None => self.insert(self.source.len(), &text),
+        }
+        Ok(())
+    }
+
+    /// Inserts the top-level `key` after the field `after`, or before the first field if there is
+    /// no such field.
+    pub fn insert_field<T: Serialize>(
+        &mut self,
+        key: &str,
+        value: &T,
+        after: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let text = to_yaml(&IndexMap::from([(key, value)]))?;
+        let offset = match after.and_then(|after| self.field(after)) {
+            Some(field) => field.span.end,
+            None => {
+                let lines = lines(&self.source);
+                match lines
+                    .iter()
+                    .position(|(_, line)| top_level_key(line).is_some())
+                {
+                    Some(first) => leading_comments_start(&lines, first),
+                    None => self.source.len(),
+                }
+            }
+        };
+        self.insert(offset, &text);
+        Ok(())
+    }
+
+    /// Removes the top-level `key` along with the comments directly above it.
+    pub fn remove_field(&mut self, key: &str) {
+        let Some(field) = self.field(key) else { return };
+        let lines = lines(&self.source);
+        let index = lines
+            .iter()
+            .position(|&(start, _)| start == field.span.start)
+            .unwrap();
+        let start = leading_comments_start(&lines, index);
+        self.source.replace_range(start..field.span.end, "");
+    }
+
+    /// Replaces the item at `index` of the sequence at the top-level `key` with `items[index]`.
+    /// The whole field is rewritten if it isn't a block sequence.
+    pub fn replace_item<T: Serialize>(
+        &mut self,
+        key: &str,
+        items: &[T],
+        index: usize,
+    ) -> anyhow::Result<()> {
+        match self.field(key).and_then(|field| field.items) {
+            Some((indent, spans)) if spans.len() == items.len() => {
+                let text = item_text(&items[index], indent)?;
+                self.source.replace_range(spans[index].clone(), &text);
+                Ok(())
+            }
+            _ => self.set_field(key, &items),
+        }
+    }
+
+    /// Appends the last of `items` to the sequence at the top-level `key`, creating the field if
+    /// it doesn't exist. The whole field is rewritten if it isn't a block sequence.
+    pub fn push_item<T: Serialize>(&mut self, key: &str, items: &[T]) -> anyhow::Result<()> {
+        let Some((item, existing)) = items.split_last() else {
+            return Ok(());
+        };
+
+        match self.field(key) {
+            Some(Field {
+                span,
+                items: Some((indent, spans)),
+            }) if spans.len() == existing.len() => {
+                let text = item_text(item, indent)?;
+                self.insert(span.end, &text);
+                Ok(())
+            }
+            _ => self.set_field(key, &items),
+        }
+    }
+
+    /// Inserts `text` at `offset`, which must be the start of a line or the end of the source.
+    fn insert(&mut self, offset: usize, text: &str) {
+        if offset == self.source.len() && !self.source.is_empty() && !self.source.ends_with('\n') {
+            self.source.push('\n');
+            self.source.push_str(text);
+        } else {
+            self.source.insert_str(offset, text);
+        }
+    }
+
+    fn field(&self, key: &str) -> Option<Field> {
+        let lines = lines(&self.source);
+        let start = lines
+            .iter()
+            .position(|(_, line)| top_level_key(line) == Some(key))?;
+
+        let next = lines[start + 1..]
+            .iter()
+            .position(|(_, line)| top_level_key(line).is_some() || is_document_marker(line))
+            .map_or(lines.len(), |i| start + 1 + i);
+
+        // Unindented comments before the next field are about that field.
+        let last = (start + 1..next)
+            .rev()
+            .find(|&i| !is_trailing(lines[i].1, 0))
+            .unwrap_or(start);
+
+        let span = lines[start].0..line_end(&lines, last);
+        let (_, value) = top_level_field(lines[start].1)?;
+        let items = if is_empty_or_comment(value) {
+            sequence_items(&lines, start + 1..last + 1)
+        } else {
+            None
+        };
+
+        Some(Field { span, items })
+    }
+}
+
+/// Each line with its starting offset, including the line terminator.
+fn lines(source: &str) -> Vec<(usize, &str)> {
+    source
+        .split_inclusive('\n')
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some((start, line))
+        })
+        .collect()
+}
+
+fn line_end(lines: &[(usize, &str)], index: usize) -> usize {
+    let (start, line) = lines[index];
+    start + line.len()
+}
+
+/// The offset of the unindented comments directly above the line at `index`, or of the line
+/// itself if there are none.
+fn leading_comments_start(lines: &[(usize, &str)], index: usize) -> usize {
+    let first = lines[..index]
+        .iter()
+        .rposition(|(_, line)| !line.starts_with('#'))
+        .map_or(0, |i| i + 1);
+    lines[first].0
+}
+
+/// The items of a block sequence in `range` of `lines` and their indentation, or `None` if the
+/// lines are something else.
+fn sequence_items(
+    lines: &[(usize, &str)],
+    range: Range<usize>,
+) -> Option<(usize, Vec<Range<usize>>)> {
+    let first = range.clone().find(|&i| !is_empty_or_comment(lines[i].1))?;
+    let indent = indentation(lines[first].1);
+    if !is_item_start(lines[first].1, indent) {
+        return None;
+    }
+
+    let starts = (first..range.end)
+        .filter(|&i| is_item_start(lines[i].1, indent))
+        .collect::<Vec<_>>();
+
+    let mut items = Vec::with_capacity(starts.len());
+    for (n, &start) in starts.iter().enumerate() {
+        let next = starts.get(n + 1).copied().unwrap_or(range.end);
+        // Comments at the indentation of the items before the next item are about that item.
+        let last = (start + 1..next)
+            .rev()
+            .find(|&i| !is_trailing(lines[i].1, indent))
+            .unwrap_or(start);
+        items.push(lines[start].0..line_end(lines, last));
+    }
+
+    Some((indent, items))
+}
+
+/// Serializes `value` with multi-line strings as literal block scalars, as inline patches are
+/// usually written.
+fn to_yaml<T: Serialize>(value: &T) -> anyhow::Result<String> {
+    struct BlockStrings<'a>(&'a Value);
+
+    impl Serialize for BlockStrings<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self.0 {
+                Value::String(s)
+                    if s.contains('\n')
+                        && !s.starts_with([' ', '\t'])
+                        && s.lines().all(|line| line.trim_end() == line) =>
+                {
+                    serde_saphyr::LitStr(s).serialize(serializer)
+                }
+                Value::Array(items) => serializer.collect_seq(items.iter().map(BlockStrings)),
+                Value::Object(map) => serializer
+                    .collect_map(map.iter().map(|(key, value)| (key, BlockStrings(value)))),
+                value => value.serialize(serializer),
+            }
+        }
+    }
+
+    yaml::to_string(&BlockStrings(&json::to_value(value)?))
+}
+
+/// The serialized `item` as an item of a block sequence at `indent`.
+fn item_text<T: Serialize>(item: &T, indent: usize) -> anyhow::Result<String> {
+    let text = to_yaml(&[item])?;
+    let padding = " ".repeat(indent);
+    Ok(text
+        .split_inclusive('\n')
+        .map(|line| {
+            if line.trim().is_empty() {
+                line.to_string()
+            } else {
+                format!("{padding}{line}")
+            }
+        })
+        .collect())
+}
+
+fn top_level_key(line: &str) -> Option<&str> {
+    top_level_field(line).map(|(key, _)| key)
+}
+
+/// The key and the rest of the line after the `:` of a line that starts a top-level field.
+fn top_level_field(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_end();
+    if line.is_empty()
+        || line.starts_with([' ', '\t', '-', '#', '%', '[', '{', '?'])
+        || is_document_marker(line)
+    {
+        return None;
+    }
+
+    let (key, rest) = match line.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = line[1..].find(quote)? + 1;
+            (&line[1..end], &line[end + 1..])
+        }
+        _ => {
+            let end = line.char_indices().find_map(|(i, c)| {
+                (c == ':' && (line[i + 1..].is_empty() || line[i + 1..].starts_with([' ', '\t'])))
+                    .then_some(i)
+            })?;
+            (line[..end].trim_end(), &line[end..])
+        }
+    };
+
+    let value = rest.trim_start().strip_prefix(':')?;
+    Some((key, value))
+}
+
+fn is_document_marker(line: &str) -> bool {
+    line.starts_with("---") || line.trim_end() == "..."
+}
+
+fn is_empty_or_comment(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || line.starts_with('#')
+}
+
+/// Whether the line is blank or a comment indented at most `indent`.
+fn is_trailing(line: &str, indent: usize) -> bool {
+    line.trim().is_empty() || (line.trim_start().starts_with('#') && indentation(line) <= indent)
+}
+
+fn is_item_start(line: &str, indent: usize) -> bool {
+    indentation(line) == indent
+        && line[indent..]
+            .strip_prefix('-')
+            .is_some_and(|rest| rest.trim().is_empty() || rest.starts_with([' ', '\t']))
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+# The app.
+apiVersion: kustomize.config.k8s.io/v1beta1
+kind: Kustomization
+
+namespace: old # overridden in prod
+resources:
+  # The workloads.
+  - deployment.yaml
+  - service.yaml # keep
+
+# Images are bumped by the release bot.
+images:
+- name: app
+  newTag: v1
+# Sidecar.
+- name: proxy
+  newTag: v2
+";
+
+    #[test]
+    fn set_field() {
+        let mut doc = Document::new(SOURCE.into());
+        doc.set_field("namespace", &"new").unwrap();
+        doc.set_field("namePrefix", &"dev-").unwrap();
+        assert_eq!(
+            doc.as_str(),
+            SOURCE.replace("namespace: old # overridden in prod\n", "namespace: new\n")
+                + "namePrefix: dev-\n"
+        );
+    }
+
+    #[test]
+    fn replace_item() {
+        let mut doc = Document::new(SOURCE.into());
+        let images = [
+            IndexMap::from([("name", "app"), ("newTag", "v1")]),
+            IndexMap::from([("name", "proxy"), ("digest", "sha256:abc")]),
+        ];
+        doc.replace_item("images", &images, 1).unwrap();
+        assert_eq!(
+            doc.as_str(),
+            SOURCE.replace(
+                "- name: proxy\n  newTag: v2\n",
+                "- name: proxy\n  digest: sha256:abc\n"
+            )
+        );
+    }
+
+    #[test]
+    fn push_item() {
+        let mut doc = Document::new(SOURCE.into());
+        doc.push_item(
+            "resources",
+            &["deployment.yaml", "service.yaml", "hpa.yaml"],
+        )
+        .unwrap();
+        doc.push_item("components", &["../components/tls"]).unwrap();
+        assert_eq!(
+            doc.as_str(),
+            SOURCE.replace(
+                "  - service.yaml # keep\n",
+                "  - service.yaml # keep\n  - hpa.yaml\n"
+            ) + "components:\n  - ../components/tls\n"
+        );
+    }
+
+    #[test]
+    fn insert_and_remove_fields() {
+        let mut doc = Document::new(SOURCE.into());
+        doc.remove_field("images");
+        doc.remove_field("apiVersion");
+        doc.insert_field("apiVersion", &"v1", None).unwrap();
+        doc.insert_field("namePrefix", &"dev-", Some("resources"))
+            .unwrap();
+        assert_eq!(
+            doc.as_str(),
+            "\
+apiVersion: v1
+kind: Kustomization
+
+namespace: old # overridden in prod
+resources:
+  # The workloads.
+  - deployment.yaml
+  - service.yaml # keep
+namePrefix: dev-
+
+"
+        );
+    }
+
+    #[test]
+    fn push_item_rewrites_flow_sequences() {
+        let mut doc = Document::new("resources: [a.yaml]\nnamespace: ns".into());
+        doc.push_item("resources", &["a.yaml", "b.yaml"]).unwrap();
+        assert_eq!(
+            doc.as_str(),
+            "resources:\n  - a.yaml\n  - b.yaml\nnamespace: ns"
+        );
+    }
+
+    #[test]
+    fn top_level_keys() {
+        for (line, key) in [
+            ("namespace: ns\n", Some("namespace")),
+            ("resources:\n", Some("resources")),
+            ("\"quoted\": x", Some("quoted")),
+            ("url: http://example.com", Some("url")),
+            ("  nested: x", None),
+            ("- item", None),
+            ("# comment: x", None),
+            ("---", None),
+            ("plain scalar", None),
+        ] {
+            assert_eq!(top_level_key(line), key, "for {line:?}");
+        }
+    }
+}
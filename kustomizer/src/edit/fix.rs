@@ -0,0 +1,235 @@
+use std::mem;
+
+use anyhow::Context as _;
+use json::Value;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use super::ManifestFile;
+use crate::{
+    PathExt as _,
+    manifest::{Label, Patch, Symbol, TypeMeta},
+    yaml,
+};
+
+impl<A, K> ManifestFile<A, K>
+where
+    A: Symbol + Default + Serialize + DeserializeOwned,
+    K: Symbol + Default + Serialize + DeserializeOwned,
+{
+    /// Rewrites deprecated fields into their replacements, as they are interpreted when loading a
+    /// kustomization, and adds `apiVersion` and `kind` if they are missing.
+    /// Returns a description of each migration, which is empty if the file was already up to date.
+    pub fn fix(&mut self) -> anyhow::Result<Vec<String>> {
+        let raw = yaml::from_str::<Value>(self.as_str())
+            .with_context(|| format!("parsing {}", self.path.pretty()))?;
+        let mut fixes = vec![];
+
+        let type_meta = &self.manifest.type_meta;
+        if type_meta.api_version.is_none() || type_meta.kind.is_none() {
+            let TypeMeta { api_version, kind } = TypeMeta::default();
+            if self.manifest.type_meta.api_version.is_none() {
+                self.document
+                    .insert_field("apiVersion", &api_version, None)?;
+                fixes.push("added `apiVersion`".to_string());
+            }
+            if self.manifest.type_meta.kind.is_none() {
+                self.document
+                    .insert_field("kind", &kind, Some("apiVersion"))?;
+                fixes.push("added `kind`".to_string());
+            }
+            self.manifest.type_meta = TypeMeta { api_version, kind };
+        }
+
+        if !self.manifest.common_labels.is_empty() {
+            let mut labels = self.manifest.labels.to_vec();
+            labels.push(Label {
+                pairs: mem::take(&mut self.manifest.common_labels),
+                include_selectors: true,
+                include_templates: false,
+                fields: Default::default(),
+            });
+            self.document.push_item("labels", &labels)?;
+            self.document.remove_field("commonLabels");
+            self.manifest.labels = labels.into_boxed_slice();
+            fixes.push("moved `commonLabels` to `labels`".to_string());
+        }
+
+        self.fix_legacy_patches(&raw, &mut fixes)?;
+
+        for (key, generators) in [
+            ("configMapGenerator", &raw["configMapGenerator"]),
+            ("secretGenerator", &raw["secretGenerator"]),
+        ] {
+            let Some(generators) = generators.as_array() else {
+                continue;
+            };
+
+            let mut items = generators.clone();
+            for index in 0..items.len() {
+                let Some(generator) = items[index].as_object() else {
+                    continue;
+                };
+                if !generator.contains_key("env") {
+                    continue;
+                }
+
+                // Renamed in place to keep the position of the field, `env` is read after `envs`.
+                let has_envs = generator.contains_key("envs");
+                let mut fixed = json::Map::new();
+                let mut env = None;
+                for (field, value) in generator {
+                    match field.as_str() {
+                        "env" if has_envs => env = Some(value.clone()),
+                        "env" => {
+                            fixed.insert("envs".into(), Value::Array(vec![value.clone()]));
+                        }
+                        _ => {
+                            fixed.insert(field.clone(), value.clone());
+                        }
+                    }
+                }
+                if let (Some(Value::Array(envs)), Some(env)) = (fixed.get_mut("envs"), env) {
+                    envs.push(env);
+                }
+
+                let name = generator
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string();
+                items[index] = Value::Object(fixed);
+                self.document.replace_item(key, &items, index)?;
+                fixes.push(format!("moved `env` to `envs` in `{key}` `{name}`"));
+            }
+        }
+
+        Ok(fixes)
+    }
+
+    /// Moves `patchesStrategicMerge` and `patchesJson6902` into `patches`, keeping the entries
+    /// as they are written so inline patches aren't reformatted.
+    fn fix_legacy_patches(&mut self, raw: &Value, fixes: &mut Vec<String>) -> anyhow::Result<()> {
+        let dir = self.path.parent().unwrap();
+        let mut items = raw["patches"].as_array().cloned().unwrap_or_default();
+        let mut patches = self.manifest.patches.to_vec();
+
+        let strategic_merge = mem::take(&mut self.manifest.patches_strategic_merge);
+        for path_or_inline in &strategic_merge {
+            // The same heuristic as when loading, an entry is a path if the file exists.
+            let item = if dir.join(path_or_inline).exists() {
+                json::json!({ "path": path_or_inline })
+            } else {
+                json::json!({ "patch": path_or_inline })
+            };
+            patches.push(json::from_value::<Patch>(item.clone())?);
+            items.push(item);
+            self.document.push_item("patches", &items)?;
+        }
+
+        let json6902 = mem::take(&mut self.manifest.patches_json6902);
+        for item in raw["patchesJson6902"].as_array().into_iter().flatten() {
+            patches.push(json::from_value::<Patch>(item.clone())?);
+            items.push(item.clone());
+            self.document.push_item("patches", &items)?;
+        }
+
+        if !strategic_merge.is_empty() {
+            self.document.remove_field("patchesStrategicMerge");
+            fixes.push("moved `patchesStrategicMerge` to `patches`".to_string());
+        }
+
+        if !json6902.is_empty() {
+            self.document.remove_field("patchesJson6902");
+            fixes.push("moved `patchesJson6902` to `patches`".to_string());
+        }
+
+        self.manifest.patches = patches.into_boxed_slice();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{edit::KustomizationFile, testutil::TempDir};
+
+    #[test]
+    fn fix() {
+        let dir = TempDir::new("fix");
+        std::fs::write(dir.join("replicas.yaml"), "").unwrap();
+        std::fs::write(
+            dir.join("kustomization.yaml"),
+            "\
+# Legacy fields.
+resources:
+  - deployment.yaml
+commonLabels:
+  app: web
+patchesStrategicMerge:
+  - replicas.yaml
+  - |-
+    apiVersion: apps/v1
+    kind: Deployment
+    metadata:
+      name: web
+patchesJson6902:
+  - target:
+      kind: Deployment
+      name: web
+    path: ops.yaml
+configMapGenerator:
+  - name: app
+    env: app.env # from the app
+",
+        )
+        .unwrap();
+
+        let mut file = KustomizationFile::load(&dir).unwrap();
+        let fixes = file.fix().unwrap();
+        file.save().unwrap();
+        let fixed = std::fs::read_to_string(file.path()).unwrap();
+        let refixes = KustomizationFile::load(&dir).unwrap().fix().unwrap();
+
+        assert_eq!(
+            fixes,
+            [
+                "added `apiVersion`",
+                "added `kind`",
+                "moved `commonLabels` to `labels`",
+                "moved `patchesStrategicMerge` to `patches`",
+                "moved `patchesJson6902` to `patches`",
+                "moved `env` to `envs` in `configMapGenerator` `app`",
+            ]
+        );
+        assert_eq!(
+            fixed,
+            "\
+apiVersion: kustomize.config.k8s.io/v1beta1
+kind: Kustomization
+# Legacy fields.
+resources:
+  - deployment.yaml
+configMapGenerator:
+  - name: app
+    envs:
+      - app.env
+labels:
+  - pairs:
+      app: web
+    includeSelectors: true
+patches:
+  - path: replicas.yaml
+  - patch: |-
+      apiVersion: apps/v1
+      kind: Deployment
+      metadata:
+        name: web
+  - target:
+      kind: Deployment
+      name: web
+    path: ops.yaml
+"
+        );
+        assert!(refixes.is_empty());
+    }
+}
@@ -42,6 +42,18 @@ pub struct FieldPath {
     segments: Box<[FieldPathSegment]>,
 }
 
+impl FieldPath {
+    /// The path to the object containing the field, if the path has more than one segment.
+    pub fn parent(&self) -> Option<FieldPath> {
+        match &*self.segments {
+            [] | [_] => None,
+            [parent @ .., _] => Some(FieldPath {
+                segments: parent.into(),
+            }),
+        }
+    }
+}
+
 impl fmt::Debug for FieldPath {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, r#""{self}""#)
@@ -131,7 +143,7 @@ impl FromStr for FieldPathSegment {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct FieldSpecs {
     specs: Vec<FieldSpec>,
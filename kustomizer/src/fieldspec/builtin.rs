@@ -10,6 +10,7 @@ const COMMON_ANNOTATIONS: &[u8] = include_bytes!("commonAnnotations.yaml");
 const IMAGES: &[u8] = include_bytes!("images.yaml");
 const METADATA_LABELS: &[u8] = include_bytes!("metadataLabels.yaml");
 const TEMPLATE_LABELS: &[u8] = include_bytes!("templateLabels.yaml");
+const TEMPLATE_ANNOTATIONS: &[u8] = include_bytes!("templateAnnotations.yaml");
 const OTHER_LABELS: &[u8] = include_bytes!("otherLabels.yaml");
 const REPLICAS: &[u8] = include_bytes!("replicas.yaml");
 const SUBJECTS: &[u8] = include_bytes!("subjects.yaml");
@@ -21,6 +22,8 @@ pub struct Builtin {
     pub images: FieldSpecs,
     pub common_annotations: FieldSpecs,
     pub template_labels: FieldSpecs,
+    /// The annotations of pod templates.
+    pub template_annotations: FieldSpecs,
     pub common_labels: FieldSpecs,
     pub replicas: FieldSpecs,
     pub subjects: FieldSpecs,
@@ -48,6 +51,8 @@ impl Builtin {
                 common_labels,
                 images: yaml::from_slice::<FieldSpecs>(IMAGES).expect("images"),
                 template_labels,
+                template_annotations: yaml::from_slice::<FieldSpecs>(TEMPLATE_ANNOTATIONS)
+                    .expect("template annotations"),
                 replicas: yaml::from_slice::<FieldSpecs>(REPLICAS).expect("replicas"),
                 metadata_labels: yaml::from_slice::<FieldSpecs>(METADATA_LABELS)
                     .expect("metadata labels"),
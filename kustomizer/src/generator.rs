@@ -1,5 +1,6 @@
 mod common;
 mod configmap;
+mod dotenv;
 mod function;
 mod secret;
 
@@ -1,13 +1,19 @@
-use anyhow::{Context, bail};
-use tokio::io::AsyncBufReadExt as _;
+use anyhow::{Context, bail, ensure};
+use compact_str::format_compact;
+use indexmap::IndexMap;
 
+use super::dotenv;
 use crate::{
-    PathExt,
-    manifest::{GeneratorOptions, KeyValuePairSources, Str},
+    ExecOptions, LoadRestrictor, PathExt,
+    manifest::{EnvFormat, GeneratorOptions, KeyValuePairSources, MaybeKeyValuePair, Str},
     resource::{Object, Resource},
+    sops,
 };
 
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 pub fn merge_options(global: &GeneratorOptions, local: &GeneratorOptions) -> GeneratorOptions {
     GeneratorOptions {
@@ -51,11 +57,15 @@ fn strip_quotes(s: &str) -> &str {
 
 /// Processes key-value pair sources and returns (`data`, `binary_data`) objects.
 /// `data` contains most of the data, `binary_data` contains non-utf8 values only when `encoding == DataEncoding::ConfigMap`.
+/// SOPS-encrypted secret sources are decrypted with `sops_age_keys` if given.
 pub(crate) async fn process_key_value_sources(
     workdir: &Path,
     sources: &KeyValuePairSources,
     encoding: DataEncoding,
     resource_type: &str,
+    load_restrictor: LoadRestrictor,
+    exec: ExecOptions,
+    sops_age_keys: Option<&str>,
 ) -> anyhow::Result<(Object, Object)> {
     let mut data = Object::new();
     let mut binary_data = Object::new();
@@ -75,58 +85,108 @@ pub(crate) async fn process_key_value_sources(
         }
     }
 
+    // The file each key was read from, to report where duplicate keys come from.
+    let mut file_keys = IndexMap::<Str, PathBuf>::new();
     for kv in &sources.files {
-        let path = workdir.join(&kv.value);
-        let key = kv.key.clone().unwrap_or_else(|| {
-            path.file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .into()
-        });
-        let value = tokio::fs::read(&path).await.with_context(|| {
-            format!("failed to read file as key value source {}", path.pretty())
-        })?;
-
-        match encoding {
-            DataEncoding::ConfigMap => match String::from_utf8(value) {
-                Ok(s) => {
-                    if binary_data.contains_key(key.as_str())
-                        || data
-                            .insert(key.to_string(), json::Value::String(s))
-                            .is_some()
-                    {
-                        bail!("duplicate key `{key}` in {resource_type} sources");
-                    }
-                }
-                Err(err) => {
-                    let value = base64_encode(err.as_bytes());
-                    if data.contains_key(key.as_str())
-                        || binary_data
-                            .insert(key.to_string(), json::Value::String(value))
-                            .is_some()
-                    {
-                        bail!("duplicate key `{key}` in {resource_type} sources");
-                    }
-                }
-            },
-            DataEncoding::Secret => {
-                if data
-                    .insert(key.to_string(), json::Value::String(base64_encode(&value)))
-                    .is_some()
-                {
-                    bail!("duplicate key `{key}` in {resource_type} sources");
-                }
+        for (key, declared) in expand_file_source(workdir, kv)? {
+            let path = workdir.join(&declared);
+            if let Some(prev) = file_keys.get(&key) {
+                bail!(
+                    "duplicate key `{key}` in {resource_type} sources from `{}` and `{}`",
+                    prev.display(),
+                    declared.display()
+                );
             }
-        };
+
+            let resolved = load_restrictor
+                .resolve_file(workdir, &declared)
+                .with_context(|| {
+                    format!("failed to read file as key value source {}", path.pretty())
+                })?;
+            let mut value = tokio::fs::read(resolved).await.with_context(|| {
+                format!("failed to read file as key value source {}", path.pretty())
+            })?;
+
+            if let DataEncoding::Secret = encoding
+                && let Some(plaintext) = decrypt_sops(&path, &value, sops_age_keys)?
+            {
+                value = plaintext.into_bytes().with_context(|| {
+                    format!("failed to read file as key value source {}", path.pretty())
+                })?;
+            }
+
+            insert_bytes(
+                (&mut data, &mut binary_data),
+                &encoding,
+                &key,
+                value,
+                resource_type,
+            )?;
+
+            file_keys.insert(key, declared);
+        }
     }
 
-    for path in &sources.envs {
-        let path = workdir.join(path);
-        let file = tokio::fs::File::open(&path)
+    for declared in &sources.envs {
+        let path = workdir.join(declared);
+        let resolved = load_restrictor
+            .resolve_file(workdir, declared)
+            .with_context(|| format!("failed to read env file {}", path.pretty()))?;
+
+        let content = tokio::fs::read(resolved)
             .await
             .with_context(|| format!("failed to read env file {}", path.pretty()))?;
-        let mut lines = tokio::io::BufReader::new(file).lines();
-        while let Some(line) = lines.next_line().await? {
+
+        if let DataEncoding::Secret = encoding
+            && let Some(plaintext) = decrypt_sops(&path, &content, sops_age_keys)?
+        {
+            let pairs = plaintext
+                .into_pairs()
+                .with_context(|| format!("failed to read env file {}", path.pretty()))?;
+            for (key, value) in pairs {
+                ensure!(
+                    !binary_data.contains_key(&key)
+                        && data
+                            .insert(
+                                key.clone(),
+                                json::Value::String(base64_encode(value.as_bytes()))
+                            )
+                            .is_none(),
+                    "duplicate key `{key}` in {resource_type} sources"
+                );
+            }
+            continue;
+        }
+
+        let content = String::from_utf8(content)
+            .with_context(|| format!("failed to read env file {}", path.pretty()))?;
+
+        if sources.env_format == EnvFormat::Dotenv {
+            let origin = path.pretty().to_string();
+            for dotenv::Entry { line, key, value } in dotenv::parse(&origin, &content)? {
+                ensure!(
+                    is_valid_data_key(key),
+                    "{origin}:{line}: invalid key `{key}`, keys must consist of alphanumeric characters, `-`, `_` or `.`"
+                );
+
+                let value = match encoding {
+                    DataEncoding::ConfigMap => value,
+                    DataEncoding::Secret => base64_encode(value.as_bytes()),
+                };
+
+                // Keys from `files` sources may be in `binary_data`, as in `insert_bytes`.
+                ensure!(
+                    !binary_data.contains_key(key)
+                        && data
+                            .insert(key.to_string(), json::Value::String(value))
+                            .is_none(),
+                    "{origin}:{line}: duplicate key `{key}` in {resource_type} sources"
+                );
+            }
+            continue;
+        }
+
+        for line in content.lines() {
             let line = line.trim();
             if line.trim().is_empty() || line.starts_with('#') {
                 continue;
@@ -141,18 +201,251 @@ pub(crate) async fn process_key_value_sources(
                 DataEncoding::Secret => base64_encode(value.as_bytes()),
             };
 
-            if data
-                .insert(key.to_string(), json::Value::String(value))
-                .is_some()
+            if binary_data.contains_key(key)
+                || data
+                    .insert(key.to_string(), json::Value::String(value))
+                    .is_some()
             {
                 bail!("duplicate key `{key}` in {resource_type} sources")
             }
         }
     }
 
+    if !sources.execs.is_empty() {
+        ensure!(
+            exec.enabled,
+            "`execs` sources run commands, which requires `--enable-exec`"
+        );
+    }
+
+    for kv in &sources.execs {
+        let value = run_exec_source(workdir, &kv.value, exec, load_restrictor)
+            .await
+            .with_context(|| format!("failed to run command for key `{}`", kv.key))?;
+        insert_bytes(
+            (&mut data, &mut binary_data),
+            &encoding,
+            &kv.key,
+            value,
+            resource_type,
+        )?;
+    }
+
     Ok((data, binary_data))
 }
 
+/// Inserts a value read from a file or command. For ConfigMaps, values that aren't valid utf-8
+/// are inserted into `binary_data`.
+fn insert_bytes(
+    (data, binary_data): (&mut Object, &mut Object),
+    encoding: &DataEncoding,
+    key: &str,
+    value: Vec<u8>,
+    resource_type: &str,
+) -> anyhow::Result<()> {
+    let duplicate = match encoding {
+        DataEncoding::ConfigMap => match String::from_utf8(value) {
+            Ok(s) => {
+                binary_data.contains_key(key)
+                    || data
+                        .insert(key.to_string(), json::Value::String(s))
+                        .is_some()
+            }
+            Err(err) => {
+                let value = base64_encode(err.as_bytes());
+                data.contains_key(key)
+                    || binary_data
+                        .insert(key.to_string(), json::Value::String(value))
+                        .is_some()
+            }
+        },
+        DataEncoding::Secret => data
+            .insert(key.to_string(), json::Value::String(base64_encode(&value)))
+            .is_some(),
+    };
+
+    ensure!(
+        !duplicate,
+        "duplicate key `{key}` in {resource_type} sources"
+    );
+    Ok(())
+}
+
+/// Runs the command of an `execs` source in `workdir` and returns its standard output verbatim.
+/// Programs given as paths are relative to `workdir` and subject to the load restrictor, others
+/// are looked up in `PATH`. The command is not run through a shell.
+async fn run_exec_source(
+    workdir: &Path,
+    command: &str,
+    exec: ExecOptions,
+    load_restrictor: LoadRestrictor,
+) -> anyhow::Result<Vec<u8>> {
+    let words = split_command(command)?;
+    let Some((program, args)) = words.split_first() else {
+        bail!("empty command");
+    };
+
+    let mut cmd = if program.contains('/') {
+        let program = load_restrictor.resolve_file(workdir, Path::new(program))?;
+        tokio::process::Command::new::<&Path>(program.as_ref())
+    } else {
+        tokio::process::Command::new(program)
+    };
+    cmd.args(args);
+
+    let now = Instant::now();
+    let output = exec.output(&mut cmd, workdir, &[]).await?;
+    ensure!(
+        output.status.success(),
+        "command `{command}` failed with status {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    tracing::info!(duration = ?now.elapsed(), cmd = ?cmd.as_std(), "executed exec source");
+
+    Ok(output.stdout)
+}
+
+/// Splits a command into words on whitespace, where single or double quotes group words.
+fn split_command(command: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word = None::<String>;
+    let mut quote = None;
+    for c in command.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.get_or_insert_default().push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            None if c.is_whitespace() => words.extend(word.take()),
+            None => word.get_or_insert_default().push(c),
+        }
+    }
+
+    ensure!(quote.is_none(), "unterminated quote in command `{command}`");
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+#[test]
+fn test_split_command() -> anyhow::Result<()> {
+    assert_eq!(
+        split_command("  git rev-parse  HEAD ")?,
+        ["git", "rev-parse", "HEAD"]
+    );
+    assert_eq!(
+        split_command(r#"sh -c 'echo "a b"' "" x"y z""#)?,
+        ["sh", "-c", r#"echo "a b""#, "", "xy z"]
+    );
+    assert!(split_command("echo 'open").is_err());
+    Ok(())
+}
+
+/// Expands a `files` entry into `(key, path)` pairs, with paths relative to `workdir`.
+/// The entry may name a single file, a directory or a glob pattern. Each regular file in a
+/// directory or matched by a pattern becomes a key named after the file, prefixed with the
+/// entry's key if one is given. Subdirectories are skipped and the pairs are ordered by path.
+fn expand_file_source(
+    workdir: &Path,
+    kv: &MaybeKeyValuePair,
+) -> anyhow::Result<Vec<(Str, PathBuf)>> {
+    let declared = Path::new(kv.value.as_str());
+    let path = workdir.join(declared);
+    // Existing files are taken literally, even if their name contains glob metacharacters.
+    let is_pattern = !path.exists() && kv.value.contains(['*', '?', '[']);
+
+    let paths = if is_pattern {
+        // Only the declared part is a pattern, the workdir may contain glob metacharacters.
+        let workdir = workdir
+            .to_str()
+            .with_context(|| format!("non-utf8 path {}", workdir.pretty()))?;
+        let pattern = format!("{}/{}", glob::Pattern::escape(workdir), kv.value);
+        let paths = glob::glob(&pattern)
+            .with_context(|| format!("invalid file source pattern `{}`", kv.value))?
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("expanding file source pattern `{}`", kv.value))?;
+        ensure!(
+            !paths.is_empty(),
+            "file source pattern `{}` did not match any files",
+            kv.value
+        );
+        paths
+    } else if path.is_dir() {
+        std::fs::read_dir(&path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect()
+            })
+            .with_context(|| format!("reading file source directory {}", path.pretty()))?
+    } else {
+        // The key defaults to the declared file name, not the name of the symlink target.
+        let key = kv.key.clone().unwrap_or_else(|| {
+            path.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into()
+        });
+        return Ok(vec![(key, declared.to_path_buf())]);
+    };
+
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        // Follows symlinks, the load restrictor decides whether their targets may be read.
+        let metadata = std::fs::metadata(&path)
+            .with_context(|| format!("reading file source {}", path.pretty()))?;
+        if metadata.is_dir() {
+            continue;
+        }
+
+        ensure!(
+            metadata.is_file(),
+            "file source {} is not a regular file",
+            path.pretty()
+        );
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let key = match &kv.key {
+            Some(prefix) => format_compact!("{prefix}{name}"),
+            None => Str::from(name),
+        };
+        let relative = path.strip_prefix(workdir).unwrap_or(&path).to_path_buf();
+        files.push((key, relative));
+    }
+
+    files.sort_by(|(_, a), (_, b)| a.cmp(b));
+    Ok(files)
+}
+
+/// Decrypts the contents of a secret source if it was encrypted with sops.
+fn decrypt_sops(
+    path: &Path,
+    content: &[u8],
+    age_keys: Option<&str>,
+) -> anyhow::Result<Option<sops::Plaintext>> {
+    let format = sops::Format::from_path(path);
+    if !sops::is_encrypted(format, content) {
+        return Ok(None);
+    }
+
+    sops::decrypt(path, format, content, &sops::Keys::new(age_keys)?).map(Some)
+}
+
+/// Whether the key is valid in the data of a ConfigMap or Secret.
+fn is_valid_data_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= 253
+        && key != "."
+        && key != ".."
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
 /// Implement matching base64 encoding to kustomize.
 /// 70 characters per line with padding.
 fn base64_encode(s: &[u8]) -> String {
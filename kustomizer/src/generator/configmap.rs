@@ -3,6 +3,7 @@ use std::borrow::Cow;
 use anyhow::Context;
 
 use crate::{
+    ExecOptions, LoadRestrictor,
     manifest::{self, Behavior, GeneratorOptions, KeyValuePairSources, TypeMeta, apiversion, kind},
     resource::{Annotations, Gvk, Metadata, Object, ResId, Resource},
 };
@@ -15,6 +16,8 @@ use super::{
 pub struct ConfigMapGenerator<'a> {
     generators: Cow<'a, [manifest::Generator]>,
     options: &'a GeneratorOptions,
+    load_restrictor: LoadRestrictor,
+    exec: ExecOptions,
 }
 
 impl<'a> ConfigMapGenerator<'a> {
@@ -25,9 +28,22 @@ impl<'a> ConfigMapGenerator<'a> {
         Self {
             generators: generators.into(),
             options,
+            load_restrictor: LoadRestrictor::default(),
+            exec: ExecOptions::default(),
         }
     }
 
+    pub fn with_load_restrictor(self, load_restrictor: LoadRestrictor) -> Self {
+        Self {
+            load_restrictor,
+            ..self
+        }
+    }
+
+    pub fn with_exec_options(self, exec: ExecOptions) -> Self {
+        Self { exec, ..self }
+    }
+
     pub fn set_options(&mut self, options: &'a GeneratorOptions) {
         self.options = options;
     }
@@ -109,6 +125,9 @@ impl ConfigMapGenerator<'_> {
             &generator.sources,
             DataEncoding::ConfigMap,
             "ConfigMapGenerator",
+            self.load_restrictor,
+            self.exec,
+            None,
         )
         .await?;
 
@@ -0,0 +1,188 @@
+use anyhow::{bail, ensure};
+
+/// An entry of a dotenv file, along with the line it starts on.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Entry<'a> {
+    pub line: usize,
+    pub key: &'a str,
+    pub value: String,
+}
+
+/// Parses the contents of a dotenv file, `origin` is used to prefix errors with `origin:line`.
+///
+/// - Blank lines and lines starting with `#` are skipped, and an `export ` prefix is ignored.
+/// - Unquoted values are trimmed and end at a `#` preceded by whitespace.
+/// - Single quoted values are taken literally.
+/// - Double quoted values support the escapes `\n`, `\r`, `\t`, `\"`, `\\` and `\$`, and a
+///   backslash at the end of a line joins it with the next.
+/// - Quoted values may span multiple lines and can only be followed by a comment.
+///
+/// Errors never include the values, which may be secret.
+pub(crate) fn parse<'a>(origin: &str, content: &'a str) -> anyhow::Result<Vec<Entry<'a>>> {
+    let mut entries = vec![];
+    let mut lines = content.lines().enumerate().map(|(i, line)| (i + 1, line));
+
+    while let Some((line, text)) = lines.next() {
+        let text = text.trim_start();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+
+        let text = text
+            .strip_prefix("export")
+            .filter(|rest| rest.starts_with([' ', '\t']))
+            .unwrap_or(text);
+
+        let Some((key, value)) = text.split_once('=') else {
+            bail!("{origin}:{line}: expected `KEY=VALUE`");
+        };
+
+        let key = key.trim();
+        ensure!(!key.is_empty(), "{origin}:{line}: missing key before `=`");
+
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('\'' | '"')) => {
+                let mut raw = String::new();
+                let mut rest = &value[1..];
+                let trailing = loop {
+                    if let Some(end) = find_closing_quote(rest, quote) {
+                        raw.push_str(&rest[..end]);
+                        break &rest[end + 1..];
+                    }
+
+                    raw.push_str(rest);
+                    raw.push('\n');
+                    match lines.next() {
+                        Some((_, text)) => rest = text,
+                        None => bail!("{origin}:{line}: unterminated quoted value for `{key}`"),
+                    }
+                };
+
+                let trailing = trailing.trim_start();
+                ensure!(
+                    trailing.is_empty() || trailing.starts_with('#'),
+                    "{origin}:{line}: unexpected characters after the quoted value for `{key}`"
+                );
+
+                if quote == '"' { unescape(&raw) } else { raw }
+            }
+            _ => {
+                let end = value
+                    .char_indices()
+                    .find(|&(i, c)| {
+                        c == '#' && value[..i].ends_with(|c: char| c.is_ascii_whitespace())
+                    })
+                    .map_or(value.len(), |(i, _)| i);
+                value[..end].trim_end().to_string()
+            }
+        };
+
+        entries.push(Entry { line, key, value });
+    }
+
+    Ok(entries)
+}
+
+/// The byte offset of the quote that closes a value, skipping escaped double quotes.
+fn find_closing_quote(s: &str, quote: char) -> Option<usize> {
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if quote == '"' => {
+                chars.next();
+            }
+            c if c == quote => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('\n') => {}
+            Some(c @ ('"' | '\\' | '$')) => out.push(c),
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_ok(content: &str) -> Vec<(usize, &str, String)> {
+        parse("test.env", content)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.line, entry.key, entry.value))
+            .collect()
+    }
+
+    #[test]
+    fn unquoted() {
+        assert_eq!(
+            parse_ok("# comment\n\nA=1\nexport B = two words  # comment\nC=a#b\nD=\n"),
+            [
+                (3, "A", "1".into()),
+                (4, "B", "two words".into()),
+                (5, "C", "a#b".into()),
+                (6, "D", "".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted() {
+        assert_eq!(
+            parse_ok(
+                "A='# not a comment \\n'\nB=\"tab\\tquote\\\"\" # comment\nC=\"line1\nline2\"\nD=\"joined \\\nline\"\nE=5"
+            ),
+            [
+                (1, "A", "# not a comment \\n".into()),
+                (2, "B", "tab\tquote\"".into()),
+                (3, "C", "line1\nline2".into()),
+                (5, "D", "joined line".into()),
+                (7, "E", "5".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        for (content, expected) in [
+            ("A=1\nNOVALUE", "test.env:2: expected `KEY=VALUE`"),
+            ("=1", "test.env:1: missing key before `=`"),
+            (
+                "\nA=\"open\nB=2",
+                "test.env:2: unterminated quoted value for `A`",
+            ),
+            (
+                "A='x' y",
+                "test.env:1: unexpected characters after the quoted value for `A`",
+            ),
+        ] {
+            let err = parse("test.env", content).unwrap_err();
+            assert_eq!(err.to_string(), expected, "for {content:?}");
+        }
+    }
+}
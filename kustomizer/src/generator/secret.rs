@@ -1,6 +1,7 @@
 use anyhow::Context;
 
 use crate::{
+    ExecOptions, LoadRestrictor,
     manifest::{self, GeneratorOptions},
     resource::{Annotations, Gvk, Metadata, Object, ResId, Resource},
 };
@@ -13,6 +14,9 @@ use super::{
 pub struct SecretGenerator<'a> {
     generators: &'a [manifest::SecretGenerator],
     options: &'a GeneratorOptions,
+    load_restrictor: LoadRestrictor,
+    exec: ExecOptions,
+    sops_age_keys: Option<&'a str>,
 }
 
 impl<'a> SecretGenerator<'a> {
@@ -20,6 +24,28 @@ impl<'a> SecretGenerator<'a> {
         Self {
             generators,
             options,
+            load_restrictor: LoadRestrictor::default(),
+            exec: ExecOptions::default(),
+            sops_age_keys: None,
+        }
+    }
+
+    pub fn with_load_restrictor(self, load_restrictor: LoadRestrictor) -> Self {
+        Self {
+            load_restrictor,
+            ..self
+        }
+    }
+
+    pub fn with_exec_options(self, exec: ExecOptions) -> Self {
+        Self { exec, ..self }
+    }
+
+    /// Decrypts SOPS-encrypted sources with these age identities instead of the ones sops would use.
+    pub fn with_sops_age_keys(self, sops_age_keys: Option<&'a str>) -> Self {
+        Self {
+            sops_age_keys,
+            ..self
         }
     }
 }
@@ -63,6 +89,9 @@ impl SecretGenerator<'_> {
             &generator.sources,
             DataEncoding::Secret,
             "SecretGenerator",
+            self.load_restrictor,
+            self.exec,
+            self.sops_age_keys,
         )
         .await?;
         assert!(
@@ -0,0 +1,484 @@
+use core::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, ensure};
+use regex::Regex;
+
+use crate::manifest::Str;
+
+/// The registry that references without one are pulled from.
+const DEFAULT_REGISTRY: &str = "docker.io";
+
+/// A parsed OCI image reference of the form `[registry[:port]/]repository[:tag][@digest]`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImageRef {
+    pub registry: Option<Str>,
+    pub port: Option<u16>,
+    pub repository: Str,
+    pub tag: Option<Str>,
+    pub digest: Option<Str>,
+}
+
+impl ImageRef {
+    /// The image name, i.e. the registry (with port) and repository without tag or digest.
+    pub fn name(&self) -> Str {
+        let mut name = Str::default();
+        if let Some(registry) = &self.registry {
+            name.push_str(registry);
+            if let Some(port) = self.port {
+                name.push(':');
+                name.push_str(&port.to_string());
+            }
+            name.push('/');
+        }
+        name.push_str(&self.repository);
+        name
+    }
+
+    /// Whether `name` (as written in `images[].name`) refers to this image, with or without a
+    /// registry, e.g. `nginx` and `docker.io/library/nginx` both refer to `docker.io/nginx:1.2`.
+    /// Names that do not parse as an image name are compared as written.
+    pub fn matches_name(&self, name: &str) -> bool {
+        if self.name() == name {
+            return true;
+        }
+        match name.parse::<ImageRef>() {
+            Ok(other) if other.tag.is_none() && other.digest.is_none() => {
+                other.qualified_name() == self.qualified_name()
+            }
+            _ => false,
+        }
+    }
+
+    /// The name with the implicit docker hub registry and `library/` namespace filled in,
+    /// e.g. `nginx` and `docker.io/nginx` become `docker.io/library/nginx`.
+    pub fn qualified_name(&self) -> Str {
+        match &self.registry {
+            Some(registry)
+                if registry == DEFAULT_REGISTRY
+                    && self.port.is_none()
+                    && !self.repository.contains('/') =>
+            {
+                format!("{DEFAULT_REGISTRY}/library/{}", self.repository).into()
+            }
+            Some(_) => self.name(),
+            None if self.repository.contains('/') => {
+                format!("{DEFAULT_REGISTRY}/{}", self.repository).into()
+            }
+            None => format!("{DEFAULT_REGISTRY}/library/{}", self.repository).into(),
+        }
+    }
+
+    /// The registry (with port) the image is pulled from.
+    pub fn qualified_registry(&self) -> Str {
+        match (&self.registry, self.port) {
+            (Some(registry), Some(port)) => format!("{registry}:{port}").into(),
+            (Some(registry), None) => registry.clone(),
+            (None, _) => DEFAULT_REGISTRY.into(),
+        }
+    }
+}
+
+/// A pattern over image names where `*` matches any (possibly empty) sequence of characters
+/// within a single path segment, i.e. excluding `/`, except for a trailing `*` which also
+/// matches nested names so that `docker.io/*` covers `docker.io/library/nginx`.
+/// A pattern without any `*` matches the name itself and any name nested below it.
+#[derive(Debug, Clone)]
+pub struct ImageGlob {
+    pattern: Str,
+    regex: Regex,
+}
+
+impl ImageGlob {
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+
+    /// Rewrites `name` according to `replacement` if it matches.
+    /// Each `*` in `replacement` is substituted by the next capture, captures may also be
+    /// referred to explicitly as `$1`, `$2`, etc.
+    /// For patterns without `*`, the remainder of a nested name is appended to `replacement`.
+    pub fn rewrite(&self, name: &str, replacement: &str) -> Option<Str> {
+        let captures = self.regex.captures(name)?;
+
+        let mut template = String::with_capacity(replacement.len());
+        let mut next = 1;
+        for c in replacement.chars() {
+            if c == '*' {
+                template.push_str(&format!("${{{next}}}"));
+                next += 1;
+            } else {
+                template.push(c);
+            }
+        }
+
+        let mut rewritten = String::new();
+        captures.expand(&template, &mut rewritten);
+        if !self.pattern.contains('*') {
+            // The implicit trailing capture of a prefix pattern.
+            rewritten.push_str(captures.name("rest").map_or("", |m| m.as_str()));
+        }
+        Some(rewritten.into())
+    }
+}
+
+impl FromStr for ImageGlob {
+    type Err = anyhow::Error;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        ensure!(!pattern.is_empty(), "image pattern must not be empty");
+
+        let regex = if pattern.contains('*') {
+            let (body, tail) = match pattern.strip_suffix('*') {
+                Some(body) => (body, "(.*)"),
+                None => (pattern, ""),
+            };
+            let parts = body.split('*').map(regex::escape).collect::<Vec<_>>();
+            format!("^{}{tail}$", parts.join("([^/]*)"))
+        } else {
+            let prefix = pattern.trim_end_matches('/');
+            format!("^{}(?<rest>/.*)?$", regex::escape(prefix))
+        };
+
+        Ok(ImageGlob {
+            pattern: pattern.into(),
+            regex: Regex::new(&regex)?,
+        })
+    }
+}
+
+impl serde::Serialize for ImageGlob {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ImageGlob {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <Str as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for ImageRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ensure!(!s.is_empty(), "image reference must not be empty");
+        ensure!(
+            !s.contains(char::is_whitespace),
+            "image reference `{s}` must not contain whitespace"
+        );
+
+        let (rest, digest) = match s.split_once('@') {
+            Some((rest, digest)) => {
+                match digest.split_once(':') {
+                    Some((algorithm, encoded)) if !algorithm.is_empty() && !encoded.is_empty() => {}
+                    _ => bail!("invalid digest `{digest}` in image reference `{s}`"),
+                }
+                (rest, Some(Str::from(digest)))
+            }
+            None => (s, None),
+        };
+
+        // The tag is separated by the last `:` after the last `/`, any earlier `:` is a port.
+        let last_component = rest.rfind('/').map_or(0, |i| i + 1);
+        let (name, tag) = match rest[last_component..].rfind(':') {
+            Some(i) => {
+                let (name, tag) = rest.split_at(last_component + i);
+                let tag = &tag[1..];
+                ensure!(
+                    is_valid_tag(tag),
+                    "invalid tag `{tag}` in image reference `{s}`"
+                );
+                (name, Some(Str::from(tag)))
+            }
+            None => (rest, None),
+        };
+
+        // Same heuristic as docker: the first component is a registry if it looks like a host.
+        let (registry, repository) = match name.split_once('/') {
+            Some((domain, repository)) if domain.contains(['.', ':']) || domain == "localhost" => {
+                (Some(domain), repository)
+            }
+            _ => (None, name),
+        };
+
+        ensure!(
+            !repository.is_empty() && !repository.split('/').any(str::is_empty),
+            "invalid repository in image reference `{s}`"
+        );
+
+        let (registry, port) = match registry.map(|registry| registry.split_once(':')) {
+            None => (None, None),
+            Some(None) => (registry, None),
+            Some(Some((host, port))) => match port.parse::<u16>() {
+                Ok(port) if !host.is_empty() => (Some(host), Some(port)),
+                _ => bail!(
+                    "invalid registry `{}` in image reference `{s}`",
+                    registry.unwrap()
+                ),
+            },
+        };
+
+        Ok(ImageRef {
+            registry: registry.map(Str::from),
+            port,
+            repository: repository.into(),
+            tag,
+            digest,
+        })
+    }
+}
+
+// Tags are limited to `[a-zA-Z0-9_.-]`, kustomize also permits `{}` for templated tags (e.g. bazel).
+fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '{' | '}'))
+}
+
+impl fmt::Display for ImageRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{tag}")?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{digest}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImageGlob, ImageRef};
+
+    #[test]
+    fn parse() {
+        // (reference, registry, port, repository, tag, digest)
+        type Case = (
+            &'static str,
+            Option<&'static str>,
+            Option<u16>,
+            &'static str,
+            Option<&'static str>,
+            Option<&'static str>,
+        );
+        #[rustfmt::skip]
+        let cases: &[Case] = &[
+            ("nginx", None, None, "nginx", None, None),
+            ("nginx:1.2.3", None, None, "nginx", Some("1.2.3"), None),
+            ("nginx@sha256:12345", None, None, "nginx", None, Some("sha256:12345")),
+            ("nginx:1.2.3@sha256:12345", None, None, "nginx", Some("1.2.3"), Some("sha256:12345")),
+            ("docker.io/nginx:1.2.3", Some("docker.io"), None, "nginx", Some("1.2.3"), None),
+            ("foo.com:443/nginx:1.2.3", Some("foo.com"), Some(443), "nginx", Some("1.2.3"), None),
+            ("foo.com:443/nginx:1.2.3@sha256:12345", Some("foo.com"), Some(443), "nginx", Some("1.2.3"), Some("sha256:12345")),
+            ("foo.com:443/nginx@sha256:12345", Some("foo.com"), Some(443), "nginx", None, Some("sha256:12345")),
+            ("registry.local:5000/app:1.0", Some("registry.local"), Some(5000), "app", Some("1.0"), None),
+            ("localhost/app", Some("localhost"), None, "app", None, None),
+            ("localhost:5000/org/app:latest", Some("localhost"), Some(5000), "org/app", Some("latest"), None),
+            ("org/app:v1", None, None, "org/app", Some("v1"), None),
+            ("gcr.io/project/nested/app:v1", Some("gcr.io"), None, "project/nested/app", Some("v1"), None),
+            ("app:{STABLE_TAG}", None, None, "app", Some("{STABLE_TAG}"), None),
+            ("registry.local:5000", None, None, "registry.local", Some("5000"), None),
+        ];
+
+        for &(reference, registry, port, repository, tag, digest) in cases {
+            let image = reference
+                .parse::<ImageRef>()
+                .unwrap_or_else(|err| panic!("failed to parse `{reference}`: {err}"));
+            assert_eq!(
+                image.registry.as_deref(),
+                registry,
+                "registry of `{reference}`"
+            );
+            assert_eq!(image.port, port, "port of `{reference}`");
+            assert_eq!(image.repository, repository, "repository of `{reference}`");
+            assert_eq!(image.tag.as_deref(), tag, "tag of `{reference}`");
+            assert_eq!(image.digest.as_deref(), digest, "digest of `{reference}`");
+            assert_eq!(image.to_string(), reference, "roundtrip of `{reference}`");
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let cases = [
+            "",
+            "nginx:",
+            "nginx@",
+            "nginx@sha256",
+            "nginx@:12345",
+            "foo.com:abc/nginx",
+            "foo.com:443/",
+            "org//app",
+            "nginx:1.0 ",
+        ];
+
+        for reference in cases {
+            assert!(
+                reference.parse::<ImageRef>().is_err(),
+                "expected `{reference}` to be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_name() {
+        // (reference, images[].name, matches)
+        let cases = [
+            ("nginx", "nginx", true),
+            ("nginx:12345", "nginx", true),
+            ("nginx@sha256:12345", "nginx", true),
+            ("nginx:1.2.3@sha256:12345", "nginx", true),
+            ("apache:12345", "nginx", false),
+            ("nginx-alpine:12345", "nginx", false),
+            ("docker.io/nginx:1.2.3", "docker.io/nginx", true),
+            ("docker.io/nginx:1.2.3", "nginx", true),
+            ("nginx:1.2.3", "docker.io/nginx", true),
+            ("nginx:1.2.3", "docker.io/library/nginx", true),
+            ("docker.io/library/nginx:1.2.3", "nginx", true),
+            ("bitnami/redis:7", "docker.io/bitnami/redis", true),
+            ("ghcr.io/nginx:1.2.3", "nginx", false),
+            ("nginx:1.2.3", "ghcr.io/nginx", false),
+            (
+                "registry.local:5000/app:1.0",
+                "registry.local:5000/app",
+                true,
+            ),
+            ("registry.local:5000/app:1.0", "registry.local", false),
+            (
+                "registry.local:5000/app:1.0",
+                "registry.local:5001/app",
+                false,
+            ),
+        ];
+
+        for (reference, name, expected) in cases {
+            let image = reference.parse::<ImageRef>().unwrap();
+            assert_eq!(
+                image.matches_name(name),
+                expected,
+                "matching `{reference}` against `{name}`"
+            );
+        }
+    }
+
+    #[test]
+    fn qualified_name() {
+        let cases = [
+            ("nginx:1.25", "docker.io/library/nginx", "docker.io"),
+            ("bitnami/redis", "docker.io/bitnami/redis", "docker.io"),
+            ("docker.io/nginx", "docker.io/library/nginx", "docker.io"),
+            ("ghcr.io/org/app", "ghcr.io/org/app", "ghcr.io"),
+            ("localhost:5000/app", "localhost:5000/app", "localhost:5000"),
+        ];
+
+        for (reference, name, registry) in cases {
+            let image = reference.parse::<ImageRef>().unwrap();
+            assert_eq!(image.qualified_name(), name);
+            assert_eq!(image.qualified_registry(), registry);
+        }
+    }
+
+    #[test]
+    fn glob_rewrite() {
+        // (pattern, replacement, name, rewritten)
+        let cases = [
+            (
+                "docker.io/library/*",
+                "mirror.corp/dockerhub/*",
+                "docker.io/library/nginx",
+                Some("mirror.corp/dockerhub/nginx"),
+            ),
+            (
+                "docker.io/*",
+                "mirror.corp/dockerhub/*",
+                "docker.io/library/nginx",
+                Some("mirror.corp/dockerhub/library/nginx"),
+            ),
+            (
+                "docker.io",
+                "mirror.corp/dockerhub",
+                "docker.io/library/nginx",
+                Some("mirror.corp/dockerhub/library/nginx"),
+            ),
+            (
+                "docker.io/",
+                "mirror.corp/dockerhub",
+                "docker.io/library/nginx",
+                Some("mirror.corp/dockerhub/library/nginx"),
+            ),
+            (
+                "docker.io",
+                "mirror.corp/dockerhub",
+                "docker.io.evil/nginx",
+                None,
+            ),
+            (
+                "ghcr.io/*/app-*",
+                "mirror.corp/$1/$2",
+                "ghcr.io/org/app-web",
+                Some("mirror.corp/org/web"),
+            ),
+            (
+                "ghcr.io/*/app-*",
+                "mirror.corp/*-*",
+                "ghcr.io/org/app-web",
+                Some("mirror.corp/org-web"),
+            ),
+            ("ghcr.io/*/app-*", "mirror.corp/*", "ghcr.io/org/web", None),
+            (
+                "quay.io/org/app",
+                "mirror.corp/app",
+                "quay.io/org/app",
+                Some("mirror.corp/app"),
+            ),
+        ];
+
+        for (pattern, replacement, name, expected) in cases {
+            let glob = pattern.parse::<ImageGlob>().unwrap();
+            assert_eq!(
+                glob.rewrite(name, replacement).as_deref(),
+                expected,
+                "rewriting `{name}` with `{pattern}` -> `{replacement}`"
+            );
+        }
+    }
+
+    #[test]
+    fn glob_wildcards_stay_within_segments_unless_trailing() {
+        // (pattern, name, matches)
+        let cases = [
+            ("*.corp", "mirror.corp", true),
+            ("*.corp", "docker.io/evil/app.corp", false),
+            ("*.internal.corp:5000", "registry.internal.corp:5000", true),
+            ("ghcr.io/org/*", "ghcr.io/org/app", true),
+            ("ghcr.io/org/*", "ghcr.io/org/team/app", true),
+            ("ghcr.io/*/*", "ghcr.io/org/team/app", true),
+            ("ghcr.io/*/app", "ghcr.io/org/team/app", false),
+            ("ghcr.io/*/app", "ghcr.io/evil/org/app", false),
+        ];
+
+        for (pattern, name, matches) in cases {
+            let glob = pattern.parse::<ImageGlob>().unwrap();
+            assert_eq!(
+                glob.is_match(name),
+                matches,
+                "matching `{name}` with `{pattern}`"
+            );
+        }
+    }
+}
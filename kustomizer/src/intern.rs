@@ -1,9 +1,9 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     hash::{Hash, Hasher},
     io,
     ops::Deref,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{LazyLock, Mutex},
 };
 
@@ -49,6 +49,8 @@ impl Hash for PathId {
 static INTERNER: LazyLock<Mutex<HashMap<&'static Path, PathId>>> = LazyLock::new(Default::default);
 
 impl PathId {
+    /// Interns the canonical form of `path`, i.e. with all symlinks resolved.
+    /// Two paths that resolve to the same file share the same `PathId`.
     pub fn make(path: impl AsRef<Path>) -> io::Result<Self> {
         let path = path.as_ref();
         let mut interner = INTERNER.lock().unwrap();
@@ -56,7 +58,9 @@ impl PathId {
             return Ok(*id);
         }
 
-        let path = path.canonicalize()?;
+        let path = path
+            .canonicalize()
+            .map_err(|err| symlink_cycle(path).unwrap_or(err))?;
         if let Some(id) = interner.get(path.as_path()) {
             return Ok(*id);
         }
@@ -71,3 +75,52 @@ impl PathId {
         Ok(id)
     }
 }
+
+/// Returns an error describing the symlink chain if following the symlinks at `path` loops.
+fn symlink_cycle(path: &Path) -> Option<io::Error> {
+    let mut seen = HashSet::new();
+    let mut chain = vec![];
+    let mut curr = path.to_path_buf();
+
+    while let Ok(target) = std::fs::read_link(&curr) {
+        if !seen.insert(curr.clone()) {
+            chain.push(curr);
+            let chain = chain
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            return Some(io::Error::other(format!("symlink cycle detected: {chain}")));
+        }
+
+        let next = curr
+            .parent()
+            .map_or_else(PathBuf::new, Path::to_path_buf)
+            .join(target);
+        chain.push(std::mem::replace(&mut curr, next));
+    }
+
+    None
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn symlink_cycles_are_detected() -> io::Result<()> {
+    let dir = std::env::temp_dir().join(format!("kustomizer-symlink-cycle-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let a = dir.join("a");
+    let b = dir.join("b");
+    let _ = std::fs::remove_file(&a);
+    let _ = std::fs::remove_file(&b);
+    std::os::unix::fs::symlink(&b, &a)?;
+    std::os::unix::fs::symlink(&a, &b)?;
+
+    let err = PathId::make(&a).unwrap_err();
+    std::fs::remove_dir_all(&dir)?;
+    assert!(
+        err.to_string().starts_with("symlink cycle detected"),
+        "unexpected error: {err}"
+    );
+
+    Ok(())
+}
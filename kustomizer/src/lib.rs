@@ -1,9 +1,13 @@
 mod build;
 pub mod dbg;
+pub mod edit;
 mod fieldspec;
 mod generator;
+mod image;
 mod intern;
+mod loader;
 pub mod manifest;
+mod output;
 mod patch;
 mod plugin;
 mod reslist;
@@ -11,16 +15,29 @@ mod resmap;
 mod resource;
 mod selector;
 mod serde_ex;
+mod sops;
+#[cfg(test)]
+mod testutil;
 mod transform;
 pub mod yaml;
 
 use core::fmt;
-use std::{ffi::OsStr, io::BufReader, mem, ops::Deref, path::Path};
+use std::{
+    ffi::OsStr,
+    io::{self, BufReader},
+    mem,
+    ops::Deref,
+    path::Path,
+};
 
 use anyhow::{Context, bail};
 
+pub use self::build::BuildOptions;
 pub use self::intern::PathId;
-pub use self::resmap::ResourceMap;
+pub use self::loader::LoadRestrictor;
+pub use self::output::DirOutput;
+pub use self::plugin::ExecOptions;
+pub use self::resmap::{OutputFormat, ResourceFilter, ResourceMap};
 
 use self::{
     manifest::{Component, Kustomization, Label, Manifest, Patch, Symbol, kind},
@@ -29,8 +46,17 @@ use self::{
 };
 
 pub async fn build(path: impl AsRef<Path>) -> anyhow::Result<ResourceMap> {
-    let kustomization = load_kustomization(path)?;
-    build::Builder::default().build_kust(&kustomization).await
+    build_with_options(path, BuildOptions::default()).await
+}
+
+pub async fn build_with_options(
+    path: impl AsRef<Path>,
+    options: BuildOptions,
+) -> anyhow::Result<ResourceMap> {
+    let kustomization = load_kustomization(path, options.load_restrictor)?;
+    build::Builder::new(options)
+        .build_kust(&kustomization)
+        .await
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -48,12 +74,18 @@ impl<T> Deref for Located<T> {
     }
 }
 
-fn load_kustomization(path: impl AsRef<Path>) -> anyhow::Result<Located<Kustomization>> {
-    load_manifest(path)
+fn load_kustomization(
+    path: impl AsRef<Path>,
+    load_restrictor: LoadRestrictor,
+) -> anyhow::Result<Located<Kustomization>> {
+    load_manifest(path, load_restrictor)
 }
 
-fn load_component(path: impl AsRef<Path>) -> anyhow::Result<Located<Component>> {
-    let component = load_manifest(path)?;
+fn load_component(
+    path: impl AsRef<Path>,
+    load_restrictor: LoadRestrictor,
+) -> anyhow::Result<Located<Component>> {
+    let component = load_manifest(path, load_restrictor)?;
     // kind is required for to be explicitly specified for a `Component` to differentiate them from a `Kustomization`.
     // apiVersion is still optional.
 
@@ -67,19 +99,24 @@ fn load_component(path: impl AsRef<Path>) -> anyhow::Result<Located<Component>>
     Ok(component)
 }
 
-fn load_manifest<A, K>(path: impl AsRef<Path>) -> anyhow::Result<Located<Manifest<A, K>>>
+fn load_manifest<A, K>(
+    path: impl AsRef<Path>,
+    load_restrictor: LoadRestrictor,
+) -> anyhow::Result<Located<Manifest<A, K>>>
 where
     A: Symbol + serde::de::DeserializeOwned,
     K: Symbol + serde::de::DeserializeOwned,
 {
     let path = path.as_ref();
-    if !path.exists() {
-        return Err(anyhow::anyhow!("path does not exist: {}", path.pretty()));
-    }
-
-    let mut base = path
-        .canonicalize()
-        .with_context(|| format!("canonicalizing path {}", path.pretty()))?;
+    let mut base = match PathId::make(path) {
+        Ok(path) => path.to_path_buf(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(anyhow::anyhow!("path does not exist: {}", path.pretty()));
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("canonicalizing path {}", path.pretty()));
+        }
+    };
 
     if base.is_dir() {
         if base.join("kustomization.yaml").exists() && base.join("kustomization.yml").exists() {
@@ -120,11 +157,15 @@ where
         let path = parent_path.join(&path_or_inline);
         // This is actually what kustomize does to detect whether it's inline or a path. Unbelievable.
         if path.exists() {
-            let resources =
-                Resource::load_many(&path).context("loading strategic merge patches")?;
+            let resolved =
+                load_restrictor.resolve_file(&parent_path, Path::new(&path_or_inline))?;
+            let resources = Resource::load_many(resolved).with_context(|| {
+                format!("loading strategic merge patches from `{}`", path.pretty())
+            })?;
             patches.extend(resources.into_iter().map(|patch| Patch::StrategicMerge {
                 patch,
                 target: None,
+                options: Default::default(),
             }));
         } else {
             let patch = yaml::from_str::<Resource>(&path_or_inline).with_context(|| {
@@ -133,6 +174,7 @@ where
             patches.push(Patch::StrategicMerge {
                 patch,
                 target: None,
+                options: Default::default(),
             });
         }
     }
@@ -153,7 +195,8 @@ where
     manifest.labels = labels.into_boxed_slice();
 
     if let Some(path) = manifest.openapi.as_ref().map(|api| &api.path) {
-        openapi::v2::Spec::set_global_default_path(parent_path.join(path))?;
+        let path = load_restrictor.resolve_file(&parent_path, path)?;
+        openapi::v2::Spec::set_global_default_path(path)?;
     }
 
     Ok(Located {
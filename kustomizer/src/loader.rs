@@ -0,0 +1,72 @@
+use core::fmt;
+use std::{path::Path, str::FromStr};
+
+use anyhow::{Context, bail};
+
+use crate::{PathExt, PathId};
+
+/// Restricts which files a kustomization may load, see kustomize's `--load-restrictor` flag.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LoadRestrictor {
+    /// Files must be located in or below the kustomization root once symlinks are resolved.
+    RootOnly,
+    /// Files may be loaded from anywhere.
+    #[default]
+    None,
+}
+
+impl LoadRestrictor {
+    /// Resolves the file at `path` (relative to `root`) through `PathId::make` and checks the
+    /// symlink-resolved target against the restriction.
+    /// Errors refer to the declared path rather than the resolved one.
+    pub fn resolve_file(self, root: &Path, path: &Path) -> anyhow::Result<PathId> {
+        let declared = root.join(path);
+        let resolved = PathId::make(&declared)
+            .with_context(|| format!("resolving path `{}`", declared.pretty()))?;
+        self.check(root, &declared, resolved)?;
+        Ok(resolved)
+    }
+
+    /// Checks the already resolved file `resolved` (declared as `declared`) against the restriction.
+    /// Only files are subject to load restrictions, kustomizations may live anywhere.
+    pub fn check(self, root: &Path, declared: &Path, resolved: PathId) -> anyhow::Result<()> {
+        match self {
+            LoadRestrictor::None => Ok(()),
+            LoadRestrictor::RootOnly => {
+                if !resolved.starts_with(root) {
+                    bail!(
+                        "security; file `{}` (resolved to `{}`) is not in or below `{}`",
+                        declared.pretty(),
+                        resolved.pretty(),
+                        root.pretty()
+                    );
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for LoadRestrictor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "LoadRestrictionsRootOnly" => Ok(LoadRestrictor::RootOnly),
+            "LoadRestrictionsNone" => Ok(LoadRestrictor::None),
+            _ => bail!(
+                "invalid load restrictor `{s}`, expected `LoadRestrictionsRootOnly` or `LoadRestrictionsNone`"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for LoadRestrictor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadRestrictor::RootOnly => write!(f, "LoadRestrictionsRootOnly"),
+            LoadRestrictor::None => write!(f, "LoadRestrictionsNone"),
+        }
+    }
+}
@@ -1,6 +1,17 @@
-use std::{io::Write, path::PathBuf};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use clap::Parser;
+use kustomizer::{
+    BuildOptions, DirOutput, ExecOptions, LoadRestrictor, OutputFormat, PathExt as _,
+    ResourceFilter,
+    edit::{self, ComponentFile, KustomizationFile, ManifestFile},
+    manifest::{Patch, Str, Symbol, Target},
+};
+use serde::{Serialize, de::DeserializeOwned};
 use tracing_subscriber::layer::SubscriberExt as _;
 
 /// A fast kustomize implementation in Rust.
@@ -21,19 +32,54 @@ struct Args {
 enum Command {
     /// Build a kustomization target from a directory.
     Build {
-        /// Ignored, accepted for compatibility with kustomize.
-        #[clap(long, default_value = "")]
-        load_restrictor: String,
-
-        /// Ignored, accepted for compatibility with kustomize.
+        #[clap(flatten)]
+        output: OutputArgs,
+        #[clap(flatten)]
+        args: BuildArgs,
+    },
+    /// Inspect the images referenced by a kustomization.
+    Images {
+        #[clap(subcommand)]
+        subcommand: Images,
+    },
+    /// Edit a kustomization file in place, preserving the comments and formatting of the fields
+    /// that aren't edited.
+    Edit {
+        /// Path to the directory containing kustomization.yaml.
+        #[clap(long, global = true, default_value = ".")]
+        dir: PathBuf,
+        #[clap(subcommand)]
+        subcommand: Edit,
+    },
+    /// Create a kustomization file in a directory.
+    Create {
+        /// Add the Kubernetes resources found in the directory.
         #[clap(long, default_value_t = false)]
-        enable_alpha_plugins: bool,
-
-        /// Ignored, accepted for compatibility with kustomize.
+        autodetect: bool,
+        /// Also search subdirectories when autodetecting, directories with a kustomization are
+        /// added as a whole.
+        #[clap(long, default_value_t = false, requires = "autodetect")]
+        recursive: bool,
+        /// Resources to add, glob patterns are expanded relative to the directory.
+        #[clap(long, value_delimiter = ',')]
+        resources: Vec<String>,
+        /// Set the namespace of all resources.
+        #[clap(long)]
+        namespace: Option<String>,
+        /// Prefix the names of all resources.
+        #[clap(long)]
+        nameprefix: Option<String>,
+        /// Path to the directory to create kustomization.yaml in.
+        #[clap(long, default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Rewrite deprecated fields of a kustomization file into their replacements.
+    Fix {
+        /// Report the deprecated fields and fail instead of rewriting them.
         #[clap(long, default_value_t = false)]
-        enable_exec: bool,
-
+        check: bool,
         /// Path to the directory containing kustomization.yaml.
+        #[clap(default_value = ".")]
         dir: PathBuf,
     },
     /// Debugging utilities.
@@ -45,6 +91,355 @@ enum Command {
     Version {},
 }
 
+#[derive(clap::Args)]
+struct BuildArgs {
+    /// Restricts which files can be loaded, either `LoadRestrictionsRootOnly` or `LoadRestrictionsNone`.
+    /// Unlike kustomize, this defaults to `LoadRestrictionsNone`.
+    #[clap(long, default_value_t = LoadRestrictor::None)]
+    load_restrictor: LoadRestrictor,
+
+    /// Pin images to the digests in the given image lock file (a YAML or JSON mapping from
+    /// `repo:tag` to digest), applied to the output of the build.
+    #[clap(long)]
+    image_lock: Option<PathBuf>,
+
+    /// Fail on images that aren't pinned to a digest if any image lock is applied, and on patches,
+    /// replicas, images and label field specs that match no resources.
+    #[clap(long, default_value_t = false)]
+    strict: bool,
+
+    /// Decrypt SOPS-encrypted resources, overriding the `decrypt` field of kustomizations. Use
+    /// `--decrypt=false` to keep the encrypted form, e.g. when rendering without keys in CI.
+    #[clap(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    decrypt: Option<bool>,
+
+    /// Ignored, accepted for compatibility with kustomize.
+    #[clap(long, default_value_t = false)]
+    enable_alpha_plugins: bool,
+
+    /// Allow `execs` generator sources to run commands.
+    #[clap(long, default_value_t = false)]
+    enable_exec: bool,
+
+    /// Kill exec functions and `execs` commands that run for longer than this many seconds.
+    #[clap(long, value_name = "SECONDS")]
+    exec_timeout: Option<u64>,
+
+    /// Annotate pod templates with `checksum/<name>` for each ConfigMap and Secret they refer to,
+    /// so workloads roll out when the data changes.
+    #[clap(long, default_value_t = false)]
+    checksum_annotations: bool,
+
+    /// Only output the resources matching this target, e.g. `kind: ConfigMap` or
+    /// `{kind: Deployment, name: web, labelSelector: app=web}`. Can be repeated to select the
+    /// resources matching any of the targets.
+    #[clap(long, value_parser = parse_target)]
+    select: Vec<Target>,
+
+    /// Don't output the resources matching this target, takes the same form as `--select`. Can be
+    /// repeated.
+    #[clap(long, value_parser = parse_target)]
+    exclude: Vec<Target>,
+
+    /// Path to the directory containing kustomization.yaml.
+    dir: PathBuf,
+}
+
+fn parse_target(s: &str) -> anyhow::Result<Target> {
+    kustomizer::yaml::from_str(s)
+}
+
+impl BuildArgs {
+    async fn build(self, skip_image_locks: bool) -> anyhow::Result<kustomizer::ResourceMap> {
+        let options = BuildOptions {
+            load_restrictor: self.load_restrictor,
+            image_lock: self.image_lock,
+            skip_image_locks,
+            strict: self.strict,
+            decrypt: self.decrypt,
+            // The age identities sops would use, from the environment or the default key file.
+            sops_age_keys: None,
+            exec: ExecOptions {
+                enabled: self.enable_exec,
+                timeout: self.exec_timeout.map(Duration::from_secs),
+            },
+            checksum_annotations: self.checksum_annotations,
+        };
+        let mut resmap = kustomizer::build_with_options(self.dir, options).await?;
+        resmap.filter(&ResourceFilter {
+            select: self.select,
+            exclude: self.exclude,
+        });
+        Ok(resmap)
+    }
+}
+
+#[derive(clap::Args)]
+struct OutputArgs {
+    /// The format to print resources in: `yaml`, `json`, `jsonl` or `resourcelist`.
+    #[clap(long, default_value_t = OutputFormat::Yaml)]
+    output_format: OutputFormat,
+
+    /// Write the output to this path instead of stdout. If the path is a directory, or ends with a
+    /// `/`, each resource is written to its own file.
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+
+    /// The name of each file written into an output directory, with the placeholders `{group}`,
+    /// `{version}`, `{kind}`, `{namespace}` and `{name}`. Empty values are dropped along with an
+    /// adjacent `_`.
+    #[clap(long, default_value_t = DirOutput::default().file_template)]
+    output_file_template: String,
+
+    /// The subdirectory of each file written into an output directory, with the same placeholders
+    /// as `--output-file-template`, e.g. `{namespace}`.
+    #[clap(long, default_value = "")]
+    output_dir_template: String,
+
+    /// Remove the files written by earlier renders that aren't part of the output, as recorded in
+    /// the `.kustomizer-output` file of the directory. Requires `--output` to be a directory.
+    #[clap(long, default_value_t = false, requires = "output")]
+    prune: bool,
+}
+
+impl OutputArgs {
+    fn write(self, resmap: &kustomizer::ResourceMap) -> anyhow::Result<()> {
+        let Some(path) = self.output else {
+            let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+            resmap.write(&mut stdout, self.output_format)?;
+            stdout.flush()?;
+            return Ok(());
+        };
+
+        if path.is_dir() || path.as_os_str().to_string_lossy().ends_with('/') {
+            let output = DirOutput {
+                file_template: self.output_file_template,
+                dir_template: self.output_dir_template,
+                format: self.output_format,
+                prune: self.prune,
+            };
+            return output.write(&path, resmap);
+        }
+
+        anyhow::ensure!(
+            !self.prune,
+            "`--prune` requires `--output` to be a directory, but `{}` is not",
+            path.pretty()
+        );
+        resmap.write_file(&path, self.output_format)
+    }
+}
+
+#[derive(Parser)]
+enum Images {
+    /// Build a kustomization and print every image reference in the output, one per line.
+    ///
+    /// Image locks are not applied, so the output can be used to generate or refresh an image
+    /// lock file.
+    List {
+        #[clap(flatten)]
+        args: BuildArgs,
+    },
+}
+
+#[derive(Parser)]
+enum Edit {
+    /// Set the value of a field.
+    Set {
+        #[clap(subcommand)]
+        subcommand: EditSet,
+    },
+    /// Add an item to a field.
+    Add {
+        #[clap(subcommand)]
+        subcommand: EditAdd,
+    },
+}
+
+#[derive(Parser)]
+enum EditSet {
+    /// Override images, e.g. `app:v1`, `app@sha256:...` or `app=registry.local/app:v1`.
+    Image {
+        #[clap(required = true)]
+        images: Vec<String>,
+    },
+    /// Set the namespace of all resources.
+    Namespace { namespace: String },
+    /// Prefix the names of all resources.
+    Nameprefix { prefix: String },
+}
+
+#[derive(Parser)]
+enum EditAdd {
+    /// Add resources, glob patterns are expanded relative to the kustomization directory.
+    Resource {
+        #[clap(required = true)]
+        resources: Vec<String>,
+    },
+    /// Add labels given as `key:value`, separated by commas or spaces.
+    Label {
+        #[clap(required = true, value_delimiter = ',', value_parser = parse_label)]
+        labels: Vec<(Str, Str)>,
+        /// Don't add the labels to selectors.
+        #[clap(long, default_value_t = false)]
+        without_selector: bool,
+        /// Add the labels to pod templates, requires `--without-selector`.
+        #[clap(long, default_value_t = false, requires = "without_selector")]
+        include_templates: bool,
+        /// Overwrite the values of labels that are already set.
+        #[clap(long, short, default_value_t = false)]
+        force: bool,
+    },
+    /// Add a patch from a file or inline, optionally with a target.
+    Patch {
+        /// Path to the patch file.
+        #[clap(long, required_unless_present = "patch", conflicts_with = "patch")]
+        path: Option<PathBuf>,
+        /// The inline patch.
+        #[clap(long)]
+        patch: Option<String>,
+        #[clap(long)]
+        group: Option<String>,
+        #[clap(long)]
+        version: Option<String>,
+        #[clap(long)]
+        kind: Option<String>,
+        #[clap(long)]
+        name: Option<String>,
+        #[clap(long)]
+        namespace: Option<String>,
+        #[clap(long)]
+        label_selector: Option<String>,
+        #[clap(long)]
+        annotation_selector: Option<String>,
+    },
+}
+
+fn parse_label(s: &str) -> anyhow::Result<(Str, Str)> {
+    match s.split_once(':') {
+        Some((key, value)) if !key.is_empty() => Ok((key.into(), value.into())),
+        _ => anyhow::bail!("invalid label `{s}`, expected `key:value`"),
+    }
+}
+
+impl Edit {
+    fn run(self, dir: &Path) -> anyhow::Result<()> {
+        if edit::is_component(dir)? {
+            self.apply(ComponentFile::load(dir)?)
+        } else {
+            self.apply(KustomizationFile::load(dir)?)
+        }
+    }
+
+    fn apply<A, K>(self, mut file: ManifestFile<A, K>) -> anyhow::Result<()>
+    where
+        A: Symbol + Default + Serialize + DeserializeOwned,
+        K: Symbol + Default + Serialize + DeserializeOwned,
+    {
+        match self {
+            Edit::Set { subcommand } => match subcommand {
+                EditSet::Image { images } => {
+                    for image in &images {
+                        file.set_image(image)?;
+                    }
+                }
+                EditSet::Namespace { namespace } => file.set_namespace(&namespace)?,
+                EditSet::Nameprefix { prefix } => file.set_name_prefix(&prefix)?,
+            },
+            Edit::Add { subcommand } => match subcommand {
+                EditAdd::Resource { resources } => {
+                    let added = file.add_resources(&resources)?;
+                    for resource in &resources {
+                        if added
+                            .iter()
+                            .all(|path| path.as_os_str() != resource.as_str())
+                            && !resource.contains(['*', '?', '['])
+                        {
+                            eprintln!("resource `{resource}` is already in the kustomization");
+                        }
+                    }
+                }
+                EditAdd::Label {
+                    labels,
+                    without_selector,
+                    include_templates,
+                    force,
+                } => file.add_labels(
+                    labels.into_iter().collect(),
+                    !without_selector,
+                    include_templates,
+                    force,
+                )?,
+                EditAdd::Patch {
+                    path,
+                    patch,
+                    group,
+                    version,
+                    kind,
+                    name,
+                    namespace,
+                    label_selector,
+                    annotation_selector,
+                } => {
+                    let mut value = json::Map::new();
+                    if let Some(path) = path {
+                        value.insert("path".into(), json::to_value(path)?);
+                    }
+                    if let Some(patch) = patch {
+                        value.insert("patch".into(), patch.into());
+                    }
+
+                    let target = [
+                        ("group", group),
+                        ("version", version),
+                        ("kind", kind),
+                        ("name", name),
+                        ("namespace", namespace),
+                        ("labelSelector", label_selector),
+                        ("annotationSelector", annotation_selector),
+                    ]
+                    .into_iter()
+                    .filter_map(|(key, value)| Some((key.to_string(), value?.into())))
+                    .collect::<json::Map<_, _>>();
+                    if !target.is_empty() {
+                        value.insert("target".into(), target.into());
+                    }
+
+                    let patch = json::from_value::<Patch>(value.into())?;
+                    if !file.add_patch(patch)? {
+                        eprintln!("patch is already in the kustomization");
+                    }
+                }
+            },
+        }
+
+        file.save()
+    }
+}
+
+fn fix<A, K>(mut file: ManifestFile<A, K>, check: bool) -> anyhow::Result<()>
+where
+    A: Symbol + Default + Serialize + DeserializeOwned,
+    K: Symbol + Default + Serialize + DeserializeOwned,
+{
+    let fixes = file.fix()?;
+    for fix in &fixes {
+        eprintln!("{}: {fix}", file.path().pretty());
+    }
+
+    if check {
+        anyhow::ensure!(
+            fixes.is_empty(),
+            "{} uses deprecated fields, run `kustomizer fix` to migrate them",
+            file.path().pretty()
+        );
+    } else if !fixes.is_empty() {
+        file.save()?;
+    }
+
+    Ok(())
+}
+
 #[derive(Parser)]
 enum Debug {
     /// Build a kustomization and diff the output against the reference kustomize implementation.
@@ -90,11 +485,42 @@ async fn main() -> anyhow::Result<()> {
     };
 
     match args.command {
-        Command::Build { dir, .. } => {
-            let resmap = kustomizer::build(dir).await?;
-            let mut stdout = std::io::stdout().lock();
-            writeln!(stdout, "{resmap}")?;
-            stdout.flush()?;
+        Command::Build { output, args } => output.write(&args.build(false).await?)?,
+        Command::Images { subcommand } => match subcommand {
+            Images::List { args } => {
+                // Image locks are skipped, as pinning replaces tags with digests.
+                let resmap = args.build(true).await?;
+                let mut stdout = std::io::stdout().lock();
+                for image in resmap.images()? {
+                    writeln!(stdout, "{image}")?;
+                }
+                stdout.flush()?;
+            }
+        },
+        Command::Edit { dir, subcommand } => subcommand.run(&dir)?,
+        Command::Create {
+            autodetect,
+            recursive,
+            resources,
+            namespace,
+            nameprefix,
+            dir,
+        } => {
+            let options = edit::CreateOptions {
+                autodetect,
+                recursive,
+                resources,
+                namespace: namespace.map(Str::from),
+                name_prefix: nameprefix.map(Str::from),
+            };
+            KustomizationFile::create(&dir, options)?.save()?;
+        }
+        Command::Fix { check, dir } => {
+            if edit::is_component(&dir)? {
+                fix(ComponentFile::load(&dir)?, check)?
+            } else {
+                fix(KustomizationFile::load(&dir)?, check)?
+            }
         }
         Command::Debug { subcommand } => match subcommand {
             Debug::DiffReference { dir } => {
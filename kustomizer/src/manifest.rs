@@ -77,8 +77,22 @@ pub struct Manifest<A, K> {
     pub common_annotations: IndexMap<Str, Annotation>,
     #[serde(default, skip_serializing_if = "<[_]>::is_empty")]
     pub images: Box<[ImageTag]>,
+    /// A file mapping image references to digests that images are pinned to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_lock: Option<PathBuf>,
     #[serde(default, skip_serializing_if = "<[_]>::is_empty")]
     pub replicas: Box<[Replica]>,
+    /// Fail the build on patches, replicas, images and label field specs that match no resources.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strict: bool,
+    /// Decrypt SOPS-encrypted resources listed in `resources`, verifying their MAC and removing
+    /// the `sops` metadata.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub decrypt: bool,
+    /// Annotate pod templates with `checksum/<name>` for each ConfigMap and Secret they refer to.
+    /// Only read from the kustomization being built, as the checksums are of the final output.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub checksum_annotations: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -133,9 +147,12 @@ pub struct ImageTag {
     #[serde(default, skip_serializing_if = "Str::is_empty")]
     pub new_tag: Str,
     // `digest` is the value used to replace the original image tag.
-    // If `digest` is present `new_tag` is ignored.
+    // If both `new_tag` and `digest` are present the image is set to `new_name:new_tag@digest`.
     #[serde(default, skip_serializing_if = "Str::is_empty")]
     pub digest: Str,
+    // `tag_suffix` is appended to the original tag, it can't be combined with `new_tag` or `digest`.
+    #[serde(default, skip_serializing_if = "Str::is_empty")]
+    pub tag_suffix: Str,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -238,6 +255,29 @@ pub struct KeyValuePairSources {
     pub files: Box<[MaybeKeyValuePair]>,
     #[serde(default, skip_serializing_if = "<[_]>::is_empty")]
     pub envs: Box<[PathBuf]>,
+    /// Commands whose standard output is the value of the key, as `key=command args...`.
+    /// They are only run with `--enable-exec`.
+    #[serde(default, skip_serializing_if = "<[_]>::is_empty")]
+    pub execs: Box<[KeyValuePair]>,
+    #[serde(default, skip_serializing_if = "EnvFormat::is_default")]
+    pub env_format: EnvFormat,
+}
+
+/// How the files in `envs` are parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EnvFormat {
+    /// Each line is split on the first `=` and taken verbatim, as kustomize does.
+    #[default]
+    Kustomize,
+    /// Dotenv syntax, with `export` prefixes, quoting, escapes and multi-line values.
+    Dotenv,
+}
+
+impl EnvFormat {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 impl<'de> Deserialize<'de> for KeyValuePairSources {
@@ -256,6 +296,10 @@ impl<'de> Deserialize<'de> for KeyValuePairSources {
             envs: Vec<PathBuf>,
             // Support for legacy singular `env` field
             env: Option<PathBuf>,
+            #[serde(default)]
+            execs: Box<[KeyValuePair]>,
+            #[serde(default)]
+            env_format: EnvFormat,
         }
 
         let mut helper = Helper::deserialize(deserializer)?;
@@ -265,6 +309,8 @@ impl<'de> Deserialize<'de> for KeyValuePairSources {
             literals: helper.literals,
             files: helper.files,
             envs: helper.envs.into_boxed_slice(),
+            execs: helper.execs,
+            env_format: helper.env_format,
         })
     }
 }
@@ -372,20 +418,91 @@ pub struct Replica {
 pub enum Patch {
     OutOfLine {
         path: PathBuf,
+        #[serde(skip_serializing_if = "Option::is_none")]
         target: Option<Target>,
+        #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+        patch_type: Option<PatchType>,
+        #[serde(default, skip_serializing_if = "PatchOptions::is_default")]
+        options: PatchOptions,
     },
     Json {
         #[serde(with = "crate::serde_ex::nested_yaml")]
         patch: JsonPatch,
         target: Target,
+        #[serde(default, skip_serializing_if = "PatchOptions::is_default")]
+        options: PatchOptions,
     },
     StrategicMerge {
         #[serde(with = "crate::serde_ex::nested_yaml")]
         patch: Resource,
+        #[serde(skip_serializing_if = "Option::is_none")]
         target: Option<Target>,
+        #[serde(default, skip_serializing_if = "PatchOptions::is_default")]
+        options: PatchOptions,
     },
 }
 
+impl Patch {
+    pub fn options(&self) -> PatchOptions {
+        match self {
+            Patch::OutOfLine { options, .. }
+            | Patch::Json { options, .. }
+            | Patch::StrategicMerge { options, .. } => *options,
+        }
+    }
+}
+
+/// Options controlling whether a patch may change the identity of the resources it is applied to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PatchOptions {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_name_change: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_kind_change: bool,
+}
+
+impl PatchOptions {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The format of a patch, which is detected from its content unless given by the `type` hint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PatchType {
+    StrategicMerge,
+    Json,
+}
+
+impl PatchType {
+    /// A JSON patch is a list of operations whereas a strategic merge patch is an object, so the
+    /// first meaningful line is enough to tell them apart.
+    pub fn detect(content: &str) -> Self {
+        let first = content
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with("---"));
+
+        match first {
+            Some(line) if line.starts_with('[') || line == "-" || line.starts_with("- ") => {
+                PatchType::Json
+            }
+            _ => PatchType::StrategicMerge,
+        }
+    }
+}
+
+impl fmt::Display for PatchType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::StrategicMerge => write!(f, "strategic merge"),
+            Self::Json => write!(f, "JSON"),
+        }
+    }
+}
+
 // Untagged errors are too terrible to read, so we implement custom deserialization.
 impl<'de> Deserialize<'de> for Patch {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -397,6 +514,10 @@ impl<'de> Deserialize<'de> for Patch {
         struct OutOfLine {
             path: PathBuf,
             target: Option<Target>,
+            #[serde(default, rename = "type")]
+            patch_type: Option<PatchType>,
+            #[serde(default)]
+            options: PatchOptions,
         }
 
         let value: json::Value = Deserialize::deserialize(deserializer)?;
@@ -408,6 +529,8 @@ impl<'de> Deserialize<'de> for Patch {
                 return Ok(Patch::OutOfLine {
                     path: helper.path,
                     target: helper.target,
+                    patch_type: helper.patch_type,
+                    options: helper.options,
                 });
             } else if let Some(patch_value) = obj.get("patch") {
                 let target = if let Some(tv) = obj.get("target") {
@@ -416,29 +539,51 @@ impl<'de> Deserialize<'de> for Patch {
                     None
                 };
 
+                let options = match obj.get("options") {
+                    Some(ov) => json::from_value::<PatchOptions>(ov.clone())
+                        .map_err(serde::de::Error::custom)?,
+                    None => PatchOptions::default(),
+                };
+
                 let patch = patch_value
                     .as_str()
                     .ok_or_else(|| serde::de::Error::custom("patch field must be a string "))?;
 
-                // Try to deserialize as JsonPatch first
-                match yaml::from_str::<JsonPatch>(patch) {
-                    Ok(patch) => {
+                let patch_type = match obj.get("type") {
+                    Some(tv) => json::from_value::<PatchType>(tv.clone())
+                        .map_err(serde::de::Error::custom)?,
+                    None => PatchType::detect(patch),
+                };
+
+                return match patch_type {
+                    PatchType::Json => {
+                        let patch = yaml::from_str::<JsonPatch>(patch).map_err(|err| {
+                            serde::de::Error::custom(format!("parsing JSON patch: {err}"))
+                        })?;
                         let target = target.ok_or_else(|| {
                             serde::de::Error::custom("target field is required for Json patches")
                         })?;
 
-                        return Ok(Patch::Json { patch, target });
+                        Ok(Patch::Json {
+                            patch,
+                            target,
+                            options,
+                        })
                     }
-                    Err(json_err) => {
-                        // Otherwise, try to deserialize as StrategicMerge patch
+                    PatchType::StrategicMerge => {
                         let patch = yaml::from_str::<Resource>(patch).map_err(|err| {
                             serde::de::Error::custom(format!(
-                                "failed to deserialize patch as either JsonPatch due to `{json_err}`\nor StrategicMerge patch due to `{err}`",
+                                "parsing strategic merge patch: {err}"
                             ))
                         })?;
-                        return Ok(Patch::StrategicMerge { patch, target });
+
+                        Ok(Patch::StrategicMerge {
+                            patch,
+                            target,
+                            options,
+                        })
                     }
-                }
+                };
             }
         }
 
@@ -455,14 +600,47 @@ pub struct JsonPatch6902 {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", try_from = "UncheckedTarget")]
 pub struct Target {
+    #[serde(skip_serializing_if = "Option::is_none")]
     label_selector: Option<Selector>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     annotation_selector: Option<Selector>,
     #[serde(flatten)]
     pattern: Pattern,
 }
 
+/// `deny_unknown_fields` doesn't support `flatten`, so unknown fields are collected and rejected
+/// on conversion instead.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UncheckedTarget {
+    label_selector: Option<Selector>,
+    annotation_selector: Option<Selector>,
+    #[serde(flatten)]
+    pattern: Pattern,
+    #[serde(flatten)]
+    unknown: IndexMap<Str, json::Value>,
+}
+
+impl TryFrom<UncheckedTarget> for Target {
+    type Error = anyhow::Error;
+
+    fn try_from(target: UncheckedTarget) -> Result<Self, Self::Error> {
+        if let Some(field) = target.unknown.keys().next() {
+            anyhow::bail!(
+                "unknown field `{field}` in patch target, expected one of `group`, `version`, `kind`, `name`, `namespace`, `labelSelector`, `annotationSelector`"
+            );
+        }
+
+        Ok(Self {
+            label_selector: target.label_selector,
+            annotation_selector: target.annotation_selector,
+            pattern: target.pattern,
+        })
+    }
+}
+
 impl Target {
     pub fn matches(&self, resource: &Resource) -> bool {
         self.label_selector
@@ -478,6 +656,18 @@ impl Target {
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct Pattern {
+    #[serde(
+        with = "crate::serde_ex::opt_regex",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub group: Option<Regex>,
+    #[serde(
+        with = "crate::serde_ex::opt_regex",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub version: Option<Regex>,
     #[serde(
         with = "crate::serde_ex::opt_regex",
         default,
@@ -501,7 +691,12 @@ pub struct Pattern {
 impl Pattern {
     pub fn matches(&self, resource: &Resource) -> bool {
         resource.any_id_matches(|id| {
-            self.kind.as_ref().is_none_or(|re| re.is_match(&id.kind))
+            self.group.as_ref().is_none_or(|re| re.is_match(&id.group))
+                && self
+                    .version
+                    .as_ref()
+                    .is_none_or(|re| re.is_match(&id.version))
+                && self.kind.as_ref().is_none_or(|re| re.is_match(&id.kind))
                 && self.name.as_ref().is_none_or(|re| re.is_match(&id.name))
                 && self
                     .namespace
@@ -551,6 +746,8 @@ pub mod kind {
     define_symbol!(Component = "Component");
     define_symbol!(ResourceList = "ResourceList");
     define_symbol!(ImageTagTransformer = "ImageTagTransformer");
+    define_symbol!(ImageRegistryTransformer = "ImageRegistryTransformer");
+    define_symbol!(NamespaceTransformer = "NamespaceTransformer");
     define_symbol!(ServiceAccount = "ServiceAccount");
     define_symbol!(Namespace = "Namespace");
     define_symbol!(ConfigMapGenerator = "ConfigMapGenerator");
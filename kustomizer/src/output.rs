@@ -0,0 +1,369 @@
+use std::{
+    fs,
+    io::{self, BufWriter, Write as _},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, bail, ensure};
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{
+    PathExt as _,
+    resmap::{OutputFormat, ResourceMap},
+    resource::{ResId, Resource},
+};
+
+/// The file in an output directory that records the files written into it, relative to it, so
+/// pruning never removes files that kustomizer didn't write.
+pub const MANIFEST: &str = ".kustomizer-output";
+
+/// Writes each resource of a [`ResourceMap`] to its own file, as `kustomize build -o <dir>` does.
+#[derive(Debug, Clone)]
+pub struct DirOutput {
+    /// The path of each file relative to its subdirectory. Placeholders are `{group}`,
+    /// `{version}`, `{kind}`, `{namespace}` and `{name}`, an empty value is dropped together with
+    /// an adjacent `_` separator.
+    pub file_template: String,
+    /// The subdirectory of each file relative to the output directory, with the same placeholders
+    /// as `file_template`. Empty by default, which writes all files into the output directory.
+    pub dir_template: String,
+    pub format: OutputFormat,
+    /// Remove the files written by earlier renders that aren't part of this one. Only the files
+    /// recorded in the [`MANIFEST`] of the output directory are removed.
+    pub prune: bool,
+}
+
+impl Default for DirOutput {
+    fn default() -> Self {
+        Self {
+            file_template: "{group}_{version}_{kind}_{namespace}_{name}.yaml".into(),
+            dir_template: String::new(),
+            format: OutputFormat::Yaml,
+            prune: false,
+        }
+    }
+}
+
+impl DirOutput {
+    /// Writes the resources into `dir`, creating it if necessary. Each file is written atomically,
+    /// and all paths are checked for collisions before any file is written.
+    pub fn write(&self, dir: impl AsRef<Path>, resmap: &ResourceMap) -> anyhow::Result<()> {
+        let dir = dir.as_ref();
+        let mut files = IndexMap::<PathBuf, &Resource>::with_capacity(resmap.len());
+        for resource in resmap.iter() {
+            let id = resource.id();
+            let file = render(&self.file_template, id)?;
+            ensure!(
+                !file.as_os_str().is_empty(),
+                "output template `{}` rendered an empty file name for {id}",
+                self.file_template
+            );
+            let path = dir.join(render(&self.dir_template, id)?).join(file);
+            if let Some(existing) = files.insert(path.clone(), resource) {
+                bail!(
+                    "both {} and {id} would be written to `{}`",
+                    existing.id(),
+                    path.pretty()
+                );
+            }
+        }
+
+        let mut recorded = read_manifest(dir)?;
+        for (path, resource) in &files {
+            let parent = path.parent().unwrap();
+            fs::create_dir_all(parent)
+                .with_context(|| format!("creating directory {}", parent.pretty()))?;
+            write_atomic(path, |writer| {
+                ResourceMap::try_new([(*resource).clone()])?.write(writer, self.format)
+            })?;
+        }
+
+        let written = files
+            .keys()
+            .map(|path| path.strip_prefix(dir).unwrap().to_path_buf())
+            .collect::<IndexSet<_>>();
+        if self.prune {
+            for path in recorded.difference(&written) {
+                remove_stale(dir, path)?;
+            }
+            recorded.clear();
+        }
+        recorded.extend(written);
+
+        write_atomic(&dir.join(MANIFEST), |writer| {
+            for path in &recorded {
+                writeln!(writer, "{}", path.display())?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// The files recorded in the manifest of `dir` by earlier renders, if any.
+fn read_manifest(dir: &Path) -> anyhow::Result<IndexSet<PathBuf>> {
+    let path = dir.join(MANIFEST);
+    let manifest = match fs::read_to_string(&path) {
+        Ok(manifest) => manifest,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(IndexSet::new()),
+        Err(err) => return Err(err).with_context(|| format!("reading {}", path.pretty())),
+    };
+
+    manifest
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let recorded = PathBuf::from(line);
+            ensure!(
+                recorded
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_))),
+                "{} records `{line}`, which is not a relative path below the output directory",
+                path.pretty()
+            );
+            Ok(recorded)
+        })
+        .collect()
+}
+
+/// Removes the file `path` relative to `dir` if it still exists, and the directories it leaves
+/// empty below `dir`.
+fn remove_stale(dir: &Path, path: &Path) -> anyhow::Result<()> {
+    let full = dir.join(path);
+    match fs::remove_file(&full) {
+        Ok(()) => tracing::debug!(path = %full.display(), "pruned stale output"),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).with_context(|| format!("removing {}", full.pretty())),
+    }
+
+    for parent in path.ancestors().skip(1) {
+        if parent.as_os_str().is_empty() {
+            break;
+        }
+        // Fails if the directory isn't empty, which ends the cleanup.
+        if fs::remove_dir(dir.join(parent)).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+impl ResourceMap {
+    /// Writes the resources to the file at `path`, atomically replacing any existing file.
+    pub fn write_file(&self, path: impl AsRef<Path>, format: OutputFormat) -> anyhow::Result<()> {
+        write_atomic(path.as_ref(), |writer| self.write(writer, format))
+    }
+}
+
+/// Writes to a temporary file next to `path` and renames it into place, so readers see either the
+/// previous contents or the new contents but never a partial write.
+fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<fs::File>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("output path `{}` has no file name", path.pretty()))?;
+    let tmp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = (|| {
+        let mut writer = BufWriter::new(fs::File::create(&tmp)?);
+        write(&mut writer)?;
+        writer.into_inner().map_err(io::Error::from)?.sync_all()?;
+        fs::rename(&tmp, path)?;
+        anyhow::Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    result.with_context(|| format!("writing {}", path.pretty()))
+}
+
+/// Renders an output path template for `id`.
+fn render(template: &str, id: &ResId) -> anyhow::Result<PathBuf> {
+    let mut rendered = String::with_capacity(template.len());
+    // Set after an empty value that had no `_` before it, to drop the `_` after it instead.
+    let mut skip_separator = false;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        push_literal(&mut rendered, &rest[..start], skip_separator);
+
+        let Some(end) = rest[start..].find('}') else {
+            bail!("unclosed `{{` in output template `{template}`");
+        };
+        let value = match &rest[start + 1..start + end] {
+            "group" => id.gvk.group.to_string(),
+            "version" => id.gvk.version.to_string(),
+            "kind" => id.gvk.kind.as_str().to_lowercase(),
+            "namespace" => id.namespace.as_deref().unwrap_or_default().to_string(),
+            "name" => id.name.to_string(),
+            placeholder => bail!(
+                "unknown placeholder `{{{placeholder}}}` in output template `{template}`, expected one of `{{group}}`, `{{version}}`, `{{kind}}`, `{{namespace}}` or `{{name}}`"
+            ),
+        };
+
+        skip_separator = value.is_empty() && !rendered.ends_with('_');
+        if value.is_empty() && rendered.ends_with('_') {
+            rendered.pop();
+        }
+        rendered.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    push_literal(&mut rendered, rest, skip_separator);
+
+    // Empty values leave empty path segments, such as cluster-scoped resources with `{namespace}/`.
+    let path = rendered
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<PathBuf>();
+    ensure!(
+        path.components().all(|c| matches!(c, Component::Normal(_))),
+        "output template `{template}` rendered `{}` for {id}, which is not a relative path below the output directory",
+        path.display()
+    );
+    Ok(path)
+}
+
+fn push_literal(rendered: &mut String, literal: &str, skip_separator: bool) {
+    if skip_separator {
+        rendered.push_str(literal.strip_prefix('_').unwrap_or(literal));
+    } else {
+        rendered.push_str(literal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testutil::TempDir, yaml};
+
+    #[test]
+    fn render_templates() {
+        let id = |yaml: &str| yaml::from_str::<Resource>(yaml).unwrap().id().clone();
+        let deployment =
+            id("apiVersion: apps/v1\nkind: Deployment\nmetadata: {name: web, namespace: prod}");
+        let namespace = id("apiVersion: v1\nkind: Namespace\nmetadata: {name: prod}");
+
+        let file_template = &DirOutput::default().file_template;
+        assert_eq!(
+            render(file_template, &deployment).unwrap(),
+            Path::new("apps_v1_deployment_prod_web.yaml")
+        );
+        assert_eq!(
+            render(file_template, &namespace).unwrap(),
+            Path::new("v1_namespace_prod.yaml")
+        );
+        assert_eq!(
+            render("{namespace}/{kind}", &namespace).unwrap(),
+            Path::new("namespace")
+        );
+        assert_eq!(
+            render("{kind}_{namespace}.yaml", &namespace).unwrap(),
+            Path::new("namespace.yaml")
+        );
+
+        assert!(render("{kind", &namespace).is_err());
+        assert!(render("{uid}.yaml", &namespace).is_err());
+        assert!(render("../{name}.yaml", &namespace).is_err());
+    }
+
+    #[test]
+    fn write_dir() {
+        let dir = TempDir::new("output");
+        fs::create_dir_all(dir.join("stale")).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("stale/v1_configmap_old.yaml"), "").unwrap();
+        fs::write(dir.join("kustomization.yaml"), "").unwrap();
+        fs::write(dir.join("src/main.rs"), "").unwrap();
+        fs::write(
+            dir.join(MANIFEST),
+            "stale/v1_configmap_old.yaml\nv1_gone.yaml\n",
+        )
+        .unwrap();
+
+        let resmap = ResourceMap::try_new(
+            yaml::from_str::<Vec<Resource>>(
+                "
+- apiVersion: v1
+  kind: ConfigMap
+  metadata: {name: app, namespace: prod}
+- apiVersion: v1
+  kind: Namespace
+  metadata: {name: prod}
+",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let output = DirOutput {
+            dir_template: "{namespace}".into(),
+            prune: true,
+            ..Default::default()
+        };
+        output.write(&dir, &resmap).unwrap();
+
+        let mut files = vec![];
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+        let configmap = fs::read_to_string(dir.join("prod/v1_configmap_prod_app.yaml")).unwrap();
+        let manifest = fs::read_to_string(dir.join(MANIFEST)).unwrap();
+
+        fs::write(dir.join(MANIFEST), "../outside.yaml\n").unwrap();
+        let escape = output.write(&dir, &resmap).unwrap_err();
+
+        let collision = DirOutput {
+            file_template: "{version}.yaml".into(),
+            ..Default::default()
+        }
+        .write(&dir, &resmap)
+        .unwrap_err();
+
+        assert_eq!(
+            files,
+            [
+                dir.join(MANIFEST),
+                dir.join("kustomization.yaml"),
+                dir.join("prod/v1_configmap_prod_app.yaml"),
+                dir.join("src/main.rs"),
+                dir.join("v1_namespace_prod.yaml"),
+            ]
+        );
+        assert_eq!(
+            manifest,
+            "prod/v1_configmap_prod_app.yaml\nv1_namespace_prod.yaml\n"
+        );
+        assert!(
+            escape.to_string().ends_with(
+                "records `../outside.yaml`, which is not a relative path below the output directory"
+            ),
+            "{escape}"
+        );
+        assert_eq!(
+            configmap,
+            "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: app\n  namespace: prod\n"
+        );
+        assert!(
+            collision.to_string().starts_with(
+                "both v1.ConfigMap/app.prod and v1.Namespace/prod would be written to"
+            ),
+            "{collision}"
+        );
+    }
+}
@@ -1,8 +1,12 @@
 use std::{collections::HashSet, slice, str::FromStr};
 
+use anyhow::{bail, ensure};
 use json::{Value, map::Entry};
 
-use crate::resource::{Object, Resource};
+use crate::{
+    manifest::Str,
+    resource::{Object, Resource},
+};
 
 use self::openapi::v2::{
     ArrayType, InlineOrRef, ObjectType, Spec,
@@ -41,6 +45,13 @@ pub enum ListType {
 
 type ShouldRetain = bool;
 
+/// Lists the only fields of the base object that are kept, see apimachinery's `retainKeys`.
+const RETAIN_KEYS_DIRECTIVE: &str = "$retainKeys";
+/// `$setElementOrder/<field>` gives the order of the elements of the list `<field>`.
+const SET_ELEMENT_ORDER_PREFIX: &str = "$setElementOrder/";
+/// `$deleteFromPrimitiveList/<field>` lists values to remove from the primitive list `<field>`.
+const DELETE_FROM_PRIMITIVE_LIST_PREFIX: &str = "$deleteFromPrimitiveList/";
+
 #[tracing::instrument(skip_all, fields(resource = %base.id()))]
 pub fn merge_patch(base: &mut Resource, patch: Resource) -> anyhow::Result<ShouldRetain> {
     let spec = Spec::load_global_default();
@@ -78,14 +89,27 @@ fn merge_obj(
             return Ok(true);
         }
         _ => {
+            apply_retain_keys(base, &mut patch)?;
+            apply_set_element_order(base, &mut patch, spec, schema)?;
+
             for (key, value) in patch {
+                if let Some(field) = key.strip_prefix(DELETE_FROM_PRIMITIVE_LIST_PREFIX) {
+                    delete_from_primitive_list(base, field, value)?;
+                    continue;
+                }
+
                 if value.is_null() {
                     base.remove(&key);
                     continue;
                 }
 
                 match base.entry(key) {
-                    Entry::Vacant(entry) => drop(entry.insert(value)),
+                    Entry::Vacant(entry) => {
+                        let subschema = schema.and_then(|s| s.properties.get(entry.key()));
+                        if let Some(value) = without_directives(spec, value, subschema)? {
+                            entry.insert(value);
+                        }
+                    }
                     Entry::Occupied(mut entry) => {
                         let subschema = schema.and_then(|s| s.properties.get(entry.key()));
                         if !merge(spec, entry.get_mut(), value, subschema)? {
@@ -100,6 +124,218 @@ fn merge_obj(
     Ok(true)
 }
 
+/// Applies the `$retainKeys` directive, removing the fields of `base` that aren't listed.
+/// Every (non-null) field of the patch must itself be listed.
+fn apply_retain_keys(base: &mut Object, patch: &mut Object) -> anyhow::Result<()> {
+    let Some(retain_keys) = patch.shift_remove(RETAIN_KEYS_DIRECTIVE) else {
+        return Ok(());
+    };
+
+    let Value::Array(retain_keys) = retain_keys else {
+        bail!("invalid patch: `{RETAIN_KEYS_DIRECTIVE}` must be a list of field names");
+    };
+
+    let retain_keys = retain_keys
+        .iter()
+        .map(|key| {
+            key.as_str().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "invalid patch: `{RETAIN_KEYS_DIRECTIVE}` must be a list of field names, found `{key}`"
+                )
+            })
+        })
+        .collect::<anyhow::Result<HashSet<_>>>()?;
+
+    for (key, value) in patch.iter() {
+        if value.is_null()
+            || key.starts_with(SET_ELEMENT_ORDER_PREFIX)
+            || key.starts_with(DELETE_FROM_PRIMITIVE_LIST_PREFIX)
+        {
+            continue;
+        }
+
+        ensure!(
+            retain_keys.contains(key.as_str()),
+            "invalid patch: field `{key}` is not listed in `{RETAIN_KEYS_DIRECTIVE}`"
+        );
+    }
+
+    base.retain(|key, _| retain_keys.contains(key.as_str()));
+    Ok(())
+}
+
+/// Applies the `$setElementOrder/<field>` directives of `patch`.
+/// The corresponding patch list is merged first, then the elements named by the directive are
+/// ordered as given and the remaining elements are interleaved keeping their original order.
+fn apply_set_element_order(
+    base: &mut Object,
+    patch: &mut Object,
+    spec: &Spec,
+    schema: Option<&ObjectType>,
+) -> anyhow::Result<()> {
+    let directives = patch
+        .keys()
+        .filter(|key| key.starts_with(SET_ELEMENT_ORDER_PREFIX))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for directive in directives {
+        let field = &directive[SET_ELEMENT_ORDER_PREFIX.len()..];
+        let Some(Value::Array(order)) = patch.shift_remove(&directive) else {
+            bail!("invalid patch: `{directive}` must be a list");
+        };
+
+        let schema = match schema
+            .and_then(|s| s.properties.get(field))
+            .map(|s| spec.resolve(s))
+        {
+            Some(Type::Array(schema)) => Some(schema),
+            _ => None,
+        };
+        let keys = schema.map_or(&[][..], |schema| merge_keys(schema).unwrap_or_default());
+
+        if keys.is_empty() {
+            ensure!(
+                order.iter().all(|v| !v.is_object() && !v.is_array()),
+                "invalid patch: `{directive}` must be a list of primitive values"
+            );
+        } else {
+            ensure!(
+                order
+                    .iter()
+                    .all(|v| keys.iter().any(|key| v.get(key.as_str()).is_some())),
+                "invalid patch: every element of `{directive}` must have the merge key `{}`",
+                keys.join("`, `")
+            );
+        }
+
+        let original = match base.get(field) {
+            Some(Value::Array(original)) => original.clone(),
+            Some(_) => {
+                bail!("invalid patch: `{directive}` applies to `{field}`, which is not a list")
+            }
+            None => vec![],
+        };
+
+        if let Some(patch_list) = patch.shift_remove(field) {
+            let Value::Array(patch_list) = patch_list else {
+                bail!("invalid patch: `{field}` must be a list to be used with `{directive}`");
+            };
+
+            let Value::Array(bases) = base.entry(field).or_insert_with(|| Value::Array(vec![]))
+            else {
+                unreachable!("checked that `{field}` is a list above")
+            };
+
+            if !merge_array(bases, patch_list, spec, schema)? {
+                base.shift_remove(field);
+                continue;
+            }
+        }
+
+        let Some(Value::Array(merged)) = base.get_mut(field) else {
+            continue;
+        };
+
+        let index_of = |list: &[Value], item: &Value| {
+            list.iter()
+                .position(|element| same_element(keys, element, item))
+                .unwrap_or(usize::MAX)
+        };
+
+        let (mut patch_items, mut server_only): (Vec<_>, Vec<_>) = std::mem::take(merged)
+            .into_iter()
+            .partition(|item| index_of(&order, item) != usize::MAX);
+        patch_items.sort_by_key(|item| index_of(&order, item));
+        server_only.sort_by_key(|item| index_of(&original, item));
+
+        // Insert the server-only elements between the ordered ones, an element goes before the
+        // first ordered element that comes after it in the original list.
+        let mut server_only = server_only.into_iter().peekable();
+        for item in patch_items {
+            while let Some(next) = server_only.peek() {
+                let (i, j) = (index_of(&original, next), index_of(&original, &item));
+                if i == usize::MAX || j == usize::MAX || i > j {
+                    break;
+                }
+                merged.push(server_only.next().unwrap());
+            }
+            merged.push(item);
+        }
+        merged.extend(server_only);
+    }
+
+    Ok(())
+}
+
+/// Applies a `$deleteFromPrimitiveList/<field>` directive.
+fn delete_from_primitive_list(base: &mut Object, field: &str, values: Value) -> anyhow::Result<()> {
+    let Value::Array(values) = values else {
+        bail!("invalid patch: `{DELETE_FROM_PRIMITIVE_LIST_PREFIX}{field}` must be a list");
+    };
+
+    match base.get_mut(field) {
+        Some(Value::Array(list)) => list.retain(|value| !values.contains(value)),
+        Some(_) => bail!(
+            "invalid patch: `{DELETE_FROM_PRIMITIVE_LIST_PREFIX}{field}` applies to `{field}`, which is not a list"
+        ),
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// Resolves any patch directives in a value that has nothing to be merged into.
+/// Values without directives are returned as is.
+fn without_directives(
+    spec: &Spec,
+    value: Value,
+    schema: Option<&InlineOrRef<Box<Type>>>,
+) -> anyhow::Result<Option<Value>> {
+    fn has_directives(value: &Value) -> bool {
+        match value {
+            Value::Object(obj) => obj
+                .iter()
+                .any(|(key, value)| key.starts_with('$') || has_directives(value)),
+            Value::Array(items) => items.iter().any(has_directives),
+            _ => false,
+        }
+    }
+
+    if !has_directives(&value) {
+        return Ok(Some(value));
+    }
+
+    let mut base = match value {
+        Value::Object(_) => Value::Object(Object::new()),
+        Value::Array(_) => Value::Array(vec![]),
+        _ => unreachable!("only objects and arrays can contain directives"),
+    };
+
+    Ok(merge(spec, &mut base, value, schema)?.then_some(base))
+}
+
+fn merge_keys(schema: &ArrayType) -> Option<&[Str]> {
+    schema
+        .list_map_keys
+        .as_deref()
+        .or(schema.patch_merge_key.as_ref().map(slice::from_ref))
+}
+
+// Whether `item` is the list element identified by `element`, i.e. they agree on every merge key
+// that `element` has. Primitive lists compare by value.
+fn same_element(keys: &[Str], element: &Value, item: &Value) -> bool {
+    if keys.is_empty() {
+        return element == item;
+    }
+
+    let mut present = keys
+        .iter()
+        .filter_map(|key| element.get(key.as_str()).map(|value| (key, value)))
+        .peekable();
+    present.peek().is_some() && present.all(|(key, value)| item.get(key.as_str()) == Some(value))
+}
+
 // two values match if they have at least one common element and
 // corresponding elements only differ if one is an empty string
 fn array_keys_match<'a>(
@@ -182,11 +418,7 @@ fn merge_array(
     }
 
     match schema {
-        Some(schema) => match schema
-            .list_map_keys
-            .as_deref()
-            .or(schema.patch_merge_key.as_ref().map(slice::from_ref))
-        {
+        Some(schema) => match merge_keys(schema) {
             Some(keys) => {
                 for patch in patches {
                     if let Some(pos) = bases.iter().position(|base| {
@@ -197,6 +429,7 @@ fn merge_array(
                         }
                     } else if is_non_delete_patch(&patch)
                         && let Some(patch) = cleaned(patch)
+                        && let Some(patch) = without_directives(spec, patch, Some(&schema.items))?
                     {
                         bases.push(patch);
                     }
@@ -204,7 +437,10 @@ fn merge_array(
             }
             None => match schema.patch_strategy {
                 Some(strategy) => match strategy {
-                    PatchStrategy::Merge
+                    // `retainKeys` applies to the fields of objects rather than to lists, so as in
+                    // apimachinery a list is merged under `merge,retainKeys` and replaced under
+                    // plain `retainKeys`.
+                    PatchStrategy::Merge | PatchStrategy::MergeRetainKeys
                         if schema.list_type.is_none_or(|t| t != ListType::Atomic) =>
                     {
                         bases.extend(mk_non_delete_patches(patches));
@@ -225,14 +461,15 @@ fn merge_array(
 
                         return Ok(true);
                     }
-                    PatchStrategy::Merge | PatchStrategy::Replace => {
+                    PatchStrategy::Merge
+                    | PatchStrategy::Replace
+                    | PatchStrategy::RetainKeys
+                    | PatchStrategy::MergeRetainKeys => {
                         *bases = mk_non_delete_patches(patches).collect();
                     }
-                    PatchStrategy::RetainKeys => todo!("array patch strategy retainKeys"),
-                    PatchStrategy::MergeRetainKeys => {
-                        todo!("array patch strategy merge,retainKeys")
+                    PatchStrategy::Delete => {
+                        bail!("`delete` is not a valid patch strategy for a list")
                     }
-                    PatchStrategy::Delete => todo!("array patch strategy delete"),
                 },
                 None => *bases = mk_non_delete_patches(patches).collect(),
             },
@@ -1,17 +1,77 @@
-use std::{path::Path, process::Stdio, time::Instant};
+use std::{
+    path::Path,
+    process::{Output, Stdio},
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, bail};
 use tokio::io::AsyncWriteExt as _;
 
 use crate::{manifest::FunctionSpec, reslist::ResourceList, yaml};
 
+/// Controls for the external commands run by exec functions and `execs` generator sources.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecOptions {
+    /// Allow `execs` generator sources to run commands. Exec functions are always allowed.
+    pub enabled: bool,
+    /// Kill commands that take longer than this.
+    pub timeout: Option<Duration>,
+}
+
+impl ExecOptions {
+    /// Runs the command in `workdir` with `stdin` as its input and captures its output.
+    /// The command is killed if it exceeds the timeout.
+    pub(crate) async fn output(
+        &self,
+        cmd: &mut tokio::process::Command,
+        workdir: &Path,
+        stdin: &[u8],
+    ) -> anyhow::Result<Output> {
+        let mut proc = cmd
+            .current_dir(workdir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let mut input = proc.stdin.take().unwrap();
+        let write = async move {
+            input.write_all(stdin).await?;
+            // Close stdin so the process sees the end of its input.
+            drop(input);
+            Ok::<_, std::io::Error>(())
+        };
+        let run = async {
+            let (written, output) = tokio::join!(write, proc.wait_with_output());
+            written.context("write to process stdin")?;
+            output.context("wait for process")
+        };
+
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, run)
+                .await
+                .with_context(|| format!("process timed out after {timeout:?}"))?,
+            None => run.await,
+        }
+    }
+}
+
 pub struct FunctionPlugin {
     spec: FunctionSpec,
+    exec: ExecOptions,
 }
 
 impl FunctionPlugin {
     pub fn new(spec: FunctionSpec) -> Self {
-        Self { spec }
+        Self {
+            spec,
+            exec: ExecOptions::default(),
+        }
+    }
+
+    pub fn with_exec_options(self, exec: ExecOptions) -> Self {
+        Self { exec, ..self }
     }
 
     pub fn spec(&self) -> &FunctionSpec {
@@ -23,41 +83,22 @@ impl FunctionPlugin {
         workdir: &Path,
         input: &ResourceList,
     ) -> anyhow::Result<ResourceList> {
-        let (mut proc, cmd) = match self.spec() {
-            FunctionSpec::Exec(spec) => {
-                let mut cmd = tokio::process::Command::new(&spec.path);
-                cmd.args(&spec.args)
-                    .envs(&spec.env)
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .current_dir(workdir);
-                (
-                    cmd.spawn().with_context(|| {
-                        format!("spawn function command at `{}`", spec.path.display())
-                    })?,
-                    cmd,
-                )
-            }
-            FunctionSpec::Container(_spec) => {
-                anyhow::bail!("Container functions are not supported yet")
-            }
+        let FunctionSpec::Exec(spec) = self.spec() else {
+            bail!("Container functions are not supported yet")
         };
 
+        let mut cmd = tokio::process::Command::new(&spec.path);
+        cmd.args(&spec.args).envs(&spec.env);
+
         let now = Instant::now();
 
         let stdin = yaml::to_string(input)?;
-        proc.stdin
-            .as_mut()
-            .unwrap()
-            .write_all(stdin.as_bytes())
+        let output = self
+            .exec
+            .output(&mut cmd, workdir, stdin.as_bytes())
             .await
-            .context("write to function stdin")?;
+            .with_context(|| format!("run function command at `{}`", spec.path.display()))?;
 
-        let output = proc
-            .wait_with_output()
-            .await
-            .context("wait for function process")?;
         if !output.status.success() {
             bail!(
                 "function command failed with status {}: {}",
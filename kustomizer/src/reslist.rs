@@ -1,9 +1,12 @@
+use std::io;
+
 use serde::{Deserialize, Serialize};
 
 use crate::{
     manifest::{TypeMeta, apiversion, kind},
     resmap::ResourceMap,
     resource::Resource,
+    yaml,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -28,6 +31,37 @@ impl ResourceList {
         }
     }
 
+    /// Writes `resources` as the YAML of a `ResourceList` one item at a time, without collecting
+    /// them into a list first.
+    pub fn write_yaml<'a>(
+        mut writer: impl io::Write,
+        resources: impl IntoIterator<Item = &'a Resource>,
+    ) -> anyhow::Result<()> {
+        let type_meta = TypeMeta::<apiversion::ConfigV1, kind::ResourceList>::default();
+        yaml::to_io_writer(&mut writer, &type_meta)?;
+
+        let mut resources = resources.into_iter().peekable();
+        if resources.peek().is_none() {
+            writeln!(writer, "items: []")?;
+            return Ok(());
+        }
+
+        writeln!(writer, "items:")?;
+        for resource in resources {
+            // Sequences are indented under their key, as the serializer would emit `items`.
+            let item = yaml::to_string(&[resource])?;
+            for line in item.lines() {
+                if line.is_empty() {
+                    writeln!(writer)?;
+                } else {
+                    writeln!(writer, "  {line}")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn iter(&self) -> std::slice::Iter<'_, Resource> {
         self.items.iter()
     }
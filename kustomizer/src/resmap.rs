@@ -1,15 +1,76 @@
 use core::fmt;
-use std::ops::{Index, IndexMut};
+use std::{
+    io,
+    ops::{Index, IndexMut},
+    str::FromStr,
+};
 
 use anyhow::{Context, bail};
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 
 use crate::{
-    manifest::Behavior,
+    manifest::{Behavior, Str, Target},
+    reslist::ResourceList,
     resource::{ResId, Resource, annotation},
     yaml,
 };
 
+/// The format [`ResourceMap::write`] emits resources in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// YAML documents separated by `---`, as printed by kustomize.
+    #[default]
+    Yaml,
+    /// Pretty-printed JSON objects, one after another.
+    Json,
+    /// One JSON object per line.
+    Jsonl,
+    /// A single `config.kubernetes.io/v1` `ResourceList`, the input of KRM functions.
+    ResourceList,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yaml" => Ok(OutputFormat::Yaml),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "resourcelist" => Ok(OutputFormat::ResourceList),
+            _ => bail!(
+                "invalid output format `{s}`, expected `yaml`, `json`, `jsonl` or `resourcelist`"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Yaml => write!(f, "yaml"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Jsonl => write!(f, "jsonl"),
+            OutputFormat::ResourceList => write!(f, "resourcelist"),
+        }
+    }
+}
+
+/// Selects resources by [`Target`], a resource matches if it matches any of `select`, or `select`
+/// is empty, and none of `exclude`.
+#[derive(Debug, Clone, Default)]
+pub struct ResourceFilter {
+    pub select: Vec<Target>,
+    pub exclude: Vec<Target>,
+}
+
+impl ResourceFilter {
+    pub fn matches(&self, resource: &Resource) -> bool {
+        (self.select.is_empty() || self.select.iter().any(|target| target.matches(resource)))
+            && !self.exclude.iter().any(|target| target.matches(resource))
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct ResourceMap {
     resources: IndexMap<ResId, Resource>,
@@ -70,6 +131,11 @@ impl ResourceMap {
         self.resources.retain(|id, res| keep(id, res));
     }
 
+    /// Removes the resources that don't match `filter`.
+    pub fn filter(&mut self, filter: &ResourceFilter) {
+        self.retain(|_, resource| filter.matches(resource));
+    }
+
     pub fn keys(&self) -> impl ExactSizeIterator<Item = &ResId> + DoubleEndedIterator + fmt::Debug {
         self.resources.keys()
     }
@@ -90,13 +156,8 @@ impl ResourceMap {
 
         match (fst_match, snd_match) {
             (None, _) => match behavior {
-                // FIXME bug
-                // Merge should cause failure if the resource does not already exist, but causes a
-                // test failure currently.
-                Behavior::Create | Behavior::Merge => {
-                    drop(self.resources.insert(resource.id().clone(), resource))
-                }
-                Behavior::Replace => bail!(
+                Behavior::Create => drop(self.resources.insert(resource.id().clone(), resource)),
+                Behavior::Merge | Behavior::Replace => bail!(
                     "resource id `{}` does not exist, cannot {behavior}",
                     resource.id()
                 ),
@@ -151,6 +212,52 @@ impl ResourceMap {
         Ok(())
     }
 
+    /// Every image reference in the resources (per the builtin images field specs), in order of
+    /// first appearance and without duplicates.
+    pub fn images(&self) -> anyhow::Result<Vec<Str>> {
+        let field_specs = &crate::fieldspec::Builtin::load().images;
+        let mut images = IndexSet::new();
+        for resource in self.iter() {
+            // Field specs only traverse mutably.
+            let mut resource = resource.clone();
+            field_specs.apply::<String>(&mut resource, |image| {
+                images.insert(Str::from(image.as_str()));
+                Ok(())
+            })?;
+        }
+
+        Ok(images.into_iter().collect())
+    }
+
+    /// Writes the resources in the given format, serializing one resource at a time.
+    pub fn write(&self, mut writer: impl io::Write, format: OutputFormat) -> anyhow::Result<()> {
+        match format {
+            OutputFormat::Yaml => {
+                for resource in self.iter() {
+                    if self.len() > 1 {
+                        writeln!(writer, "---")?;
+                    }
+                    yaml::to_io_writer(&mut writer, resource)?;
+                }
+            }
+            OutputFormat::Json => {
+                for resource in self.iter() {
+                    json::to_writer_pretty(&mut writer, resource)?;
+                    writeln!(writer)?;
+                }
+            }
+            OutputFormat::Jsonl => {
+                for resource in self.iter() {
+                    json::to_writer(&mut writer, resource)?;
+                    writeln!(writer)?;
+                }
+            }
+            OutputFormat::ResourceList => ResourceList::write_yaml(writer, self.iter())?,
+        }
+
+        Ok(())
+    }
+
     pub fn iter(&self) -> impl ExactSizeIterator<Item = &Resource> + DoubleEndedIterator {
         self.resources.values()
     }
@@ -203,3 +310,125 @@ impl IndexMut<&ResId> for ResourceMap {
             .unwrap_or_else(|| panic!("resource with id `{id}` not in ResourceMap"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write() {
+        let resmap = ResourceMap::try_new(
+            yaml::from_str::<Vec<Resource>>(
+                "
+- apiVersion: v1
+  kind: ConfigMap
+  metadata:
+    name: script
+  data:
+    run.sh: |
+      #!/bin/sh
+
+      echo hello
+- apiVersion: apps/v1
+  kind: Deployment
+  metadata:
+    name: web
+",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let write = |format| {
+            let mut output = vec![];
+            resmap.write(&mut output, format).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(write(OutputFormat::Yaml), resmap.to_string());
+
+        let json = write(OutputFormat::Json);
+        let objects = json::Deserializer::from_str(&json)
+            .into_iter::<Resource>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(objects, resmap.iter().cloned().collect::<Vec<_>>());
+
+        let jsonl = write(OutputFormat::Jsonl);
+        let lines = jsonl
+            .lines()
+            .map(|line| json::from_str::<Resource>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, objects);
+
+        let list = yaml::from_str::<ResourceList>(&write(OutputFormat::ResourceList)).unwrap();
+        assert_eq!(list, ResourceList::new(resmap.iter().cloned()));
+
+        let empty = yaml::from_str::<ResourceList>(&{
+            let mut output = vec![];
+            ResourceMap::default()
+                .write(&mut output, OutputFormat::ResourceList)
+                .unwrap();
+            String::from_utf8(output).unwrap()
+        })
+        .unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn filter() {
+        let resmap = ResourceMap::try_new(
+            yaml::from_str::<Vec<Resource>>(
+                "
+- apiVersion: v1
+  kind: ConfigMap
+  metadata: {name: app, namespace: prod, labels: {tier: web}}
+- apiVersion: v1
+  kind: ConfigMap
+  metadata: {name: db, namespace: prod}
+- apiVersion: apps/v1
+  kind: Deployment
+  metadata: {name: web, namespace: prod, labels: {tier: web}}
+- apiVersion: apps/v1
+  kind: Deployment
+  metadata: {name: web, namespace: staging}
+",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let filtered = |select: &[&str], exclude: &[&str]| {
+            let targets = |targets: &[&str]| {
+                targets
+                    .iter()
+                    .map(|target| yaml::from_str::<Target>(target).unwrap())
+                    .collect()
+            };
+            let mut resmap = resmap.clone();
+            resmap.filter(&ResourceFilter {
+                select: targets(select),
+                exclude: targets(exclude),
+            });
+            resmap.keys().map(ToString::to_string).collect::<Vec<_>>()
+        };
+
+        assert_eq!(filtered(&[], &[]).len(), 4);
+        assert_eq!(
+            filtered(&["kind: ConfigMap"], &[]),
+            ["v1.ConfigMap/app.prod", "v1.ConfigMap/db.prod"]
+        );
+        assert_eq!(
+            filtered(&["{kind: ConfigMap, name: a.*}", "namespace: staging"], &[]),
+            ["v1.ConfigMap/app.prod", "apps.v1.Deployment/web.staging"]
+        );
+        assert_eq!(
+            filtered(&["labelSelector: tier=web"], &["kind: Deployment"]),
+            ["v1.ConfigMap/app.prod"]
+        );
+        assert_eq!(
+            filtered(&[], &["{annotationSelector: '!missing', namespace: prod}"]),
+            ["apps.v1.Deployment/web.staging"]
+        );
+    }
+}
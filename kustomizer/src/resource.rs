@@ -3,13 +3,14 @@ mod shorthash;
 mod view;
 
 use std::{
+    collections::VecDeque,
     fmt,
     io::BufReader,
     ops::{Deref, DerefMut},
     sync::LazyLock,
 };
 
-use anyhow::{Context, ensure};
+use anyhow::{Context, bail, ensure};
 use compact_str::format_compact;
 use dashmap::{DashMap, Entry};
 use indexmap::IndexMap;
@@ -20,7 +21,7 @@ use crate::{
     PathExt, PathId,
     manifest::{Annotation, Behavior, FunctionSpec, Str},
     patch::merge_patch,
-    yaml,
+    sops, yaml,
 };
 
 pub use self::refs::RefSpecs;
@@ -133,21 +134,113 @@ pub type Object = json::Map<String, json::Value>;
 
 impl Resource {
     pub fn load_many(path: impl AsRef<Path>) -> anyhow::Result<Box<[Self]>> {
-        static CACHE: LazyLock<DashMap<PathId, Box<[Resource]>>> = LazyLock::new(Default::default);
+        Self::load_many_with(path, false, None)
+    }
+
+    /// Like `load_many`, but decrypts the documents if they were encrypted with sops, verifying
+    /// their MAC and removing the `sops` metadata.
+    /// `age_keys` replace the age identities sops would use, see `BuildOptions::sops_age_keys`.
+    pub fn load_many_decrypted(
+        path: impl AsRef<Path>,
+        age_keys: Option<&str>,
+    ) -> anyhow::Result<Box<[Self]>> {
+        Self::load_many_with(path, true, age_keys)
+    }
+
+    fn load_many_with(
+        path: impl AsRef<Path>,
+        decrypt: bool,
+        age_keys: Option<&str>,
+    ) -> anyhow::Result<Box<[Self]>> {
+        #[allow(clippy::type_complexity)]
+        static CACHE: LazyLock<DashMap<(PathId, bool), Box<[Resource]>>> =
+            LazyLock::new(Default::default);
         let path = path.as_ref();
         let path = PathId::make(path)
             .with_context(|| format!("loading resources from path {}", path.pretty()))?;
 
-        match CACHE.entry(path) {
+        match CACHE.entry((path, decrypt)) {
             Entry::Occupied(e) => Ok(e.get().clone()),
             Entry::Vacant(e) => {
                 let file = BufReader::new(std::fs::File::open(path)?);
-                let resources = yaml::from_reader_multi(file)?;
+                let mut documents = yaml::from_reader_multi::<Document>(file)?;
+                if decrypt
+                    && documents
+                        .iter()
+                        .any(|Document(doc)| sops::has_metadata(doc))
+                {
+                    let mut values = documents
+                        .into_iter()
+                        .map(|Document(doc)| doc)
+                        .collect::<Vec<_>>();
+                    sops::decrypt_documents(&mut values, &sops::Keys::new(age_keys)?)
+                        .context("decrypting sops-encrypted resources")?;
+                    documents = values.into_iter().map(Document).collect();
+                }
+
+                let resources = Self::flatten_lists(documents)?;
                 Ok(e.insert(resources).value().clone())
             }
         }
     }
 
+    /// Expands documents of kind `List` (or any `*List` kind) into their items, as kustomize does.
+    /// A list with neither `items` nor other fields is empty. As in kustomize, the items of a list
+    /// are loaded after the remaining documents.
+    pub(crate) fn flatten_lists(documents: Box<[Document]>) -> anyhow::Result<Box<[Self]>> {
+        let mut queue = documents
+            .into_iter()
+            .enumerate()
+            .map(|(i, Document(value))| (None, i, value))
+            .collect::<VecDeque<(Option<Str>, usize, json::Value)>>();
+
+        let mut resources = Vec::with_capacity(queue.len());
+        while let Some((parent, i, value)) = queue.pop_front() {
+            let origin = || match &parent {
+                Some(list) => format!("item {i} of {list}"),
+                None => format!("document {i}"),
+            };
+
+            // A `*List` without `items` is an empty list, unless it has fields other than its
+            // type and metadata, such as custom resources named `*List` (kustomize#5042, #5485).
+            let is_list = |kind: &str| {
+                kind.ends_with("List")
+                    && (value.get("items").is_some()
+                        || value.as_object().is_some_and(|object| {
+                            object.keys().all(|key| {
+                                matches!(key.as_str(), "apiVersion" | "kind" | "metadata")
+                            })
+                        }))
+            };
+            match value.get("kind").and_then(|kind| kind.as_str()) {
+                Some(kind) if is_list(kind) => {
+                    let list = format_compact!("`{kind}` ({})", origin());
+                    match value.get("items") {
+                        None | Some(json::Value::Null) => {}
+                        Some(json::Value::Array(items)) => queue.extend(
+                            items
+                                .iter()
+                                .enumerate()
+                                .map(|(j, item)| (Some(list.clone()), j, item.clone())),
+                        ),
+                        _ => bail!("`items` of {list} must be a list"),
+                    }
+                }
+                _ if value.is_null() => {}
+                _ => {
+                    let resource = json::from_value::<Resource>(value);
+                    resources.push(match parent {
+                        // Keep the errors of top-level documents as they were.
+                        None => resource?,
+                        Some(_) => resource.with_context(|| format!("loading {}", origin()))?,
+                    });
+                }
+            }
+        }
+
+        Ok(resources.into_boxed_slice())
+    }
+
     pub fn load_one(path: impl AsRef<Path>) -> anyhow::Result<Self> {
         static CACHE: LazyLock<DashMap<PathId, Resource>> = LazyLock::new(Default::default);
         let path = path.as_ref();
@@ -257,6 +350,15 @@ impl Resource {
         Self::from_parts(id, root).expect("invariants should be maintained by this function")
     }
 
+    #[must_use]
+    pub fn with_kind(mut self, kind: Str) -> Self {
+        assert!(!kind.contains('/'), "kind must not contain '/'");
+        assert!(!kind.contains(','), "kind must not contain ','");
+        self.store_curr_id();
+        self.id.gvk.kind = kind;
+        self
+    }
+
     pub fn id(&self) -> &ResId {
         &self.id
     }
@@ -541,6 +643,21 @@ impl DerefMut for Annotations {
     }
 }
 
+/// A YAML document of a resource file, which is either a resource or a list of them.
+#[derive(Debug)]
+pub(crate) struct Document(json::Value);
+
+impl<'de> Deserialize<'de> for Document {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        json::Value::deserialize(deserializer)
+            .map(Document)
+            .map_err(|err| serde::de::Error::custom(format!("parsing resource: {err}")))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Res {
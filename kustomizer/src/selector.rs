@@ -1,12 +1,14 @@
 use core::fmt;
-use std::str::FromStr;
+use std::{iter::Peekable, str::FromStr, sync::LazyLock};
 
-use anyhow::bail;
-use indexmap::IndexSet;
+use anyhow::{Context as _, bail, ensure};
+use indexmap::{IndexMap, IndexSet};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
 use crate::manifest::Str;
 
+/// A Kubernetes label selector, see apimachinery's `labels.Parse` for the grammar.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Selector {
     Equality(Str, Str),
@@ -14,6 +16,9 @@ pub enum Selector {
     SetInclusion(Str, IndexSet<Str>),
     SetExclusion(Str, IndexSet<Str>),
     Existence(Str),
+    NonExistence(Str),
+    GreaterThan(Str, i64),
+    LessThan(Str, i64),
     All(Vec<Selector>),
 }
 
@@ -34,34 +39,102 @@ impl<T: StringMap + ?Sized> StringMap for &T {
 }
 
 impl Selector {
+    /// A missing map behaves like an empty one, so e.g. `env!=prod` matches resources without labels.
     pub(crate) fn matches(&self, map: Option<&impl StringMap>) -> bool {
-        let Some(m) = map else { return false };
+        let get = |key: &str| map.and_then(|m| m.get(key));
         match self {
-            Selector::Equality(key, value) => m.get(key).is_some_and(|v| v == value),
-            Selector::Inequality(key, value) => m.get(key).is_some_and(|v| v != value),
-            Selector::SetInclusion(key, values) => m.get(key).is_some_and(|v| values.contains(v)),
-            Selector::SetExclusion(key, values) => m.get(key).is_some_and(|v| !values.contains(v)),
-            Selector::Existence(key) => m.has(key),
+            Selector::Equality(key, value) => get(key).is_some_and(|v| v == value),
+            Selector::Inequality(key, value) => get(key).is_none_or(|v| v != value),
+            Selector::SetInclusion(key, values) => get(key).is_some_and(|v| values.contains(v)),
+            Selector::SetExclusion(key, values) => get(key).is_none_or(|v| !values.contains(v)),
+            Selector::Existence(key) => map.is_some_and(|m| m.has(key)),
+            Selector::NonExistence(key) => !map.is_some_and(|m| m.has(key)),
+            Selector::GreaterThan(key, bound) => get(key)
+                .and_then(|v| v.parse::<i64>().ok())
+                .is_some_and(|v| v > *bound),
+            Selector::LessThan(key, bound) => get(key)
+                .and_then(|v| v.parse::<i64>().ok())
+                .is_some_and(|v| v < *bound),
             Selector::All(selectors) => selectors.iter().all(|s| s.matches(map)),
         }
     }
 }
 
+/// Validates a label key, which is a name with an optional DNS subdomain prefix (e.g. `app.kubernetes.io/name`).
+fn validate_key(key: &str) -> anyhow::Result<()> {
+    static DNS_SUBDOMAIN: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?(\.[a-z0-9]([-a-z0-9]*[a-z0-9])?)*$").unwrap()
+    });
+
+    let name = match key.split_once('/') {
+        Some((prefix, name)) => {
+            ensure!(
+                !prefix.is_empty() && prefix.len() <= 253 && DNS_SUBDOMAIN.is_match(prefix),
+                "invalid label key `{key}`: prefix must be a DNS subdomain of at most 253 characters"
+            );
+            name
+        }
+        None => key,
+    };
+
+    ensure!(
+        !name.is_empty() && name.len() <= 63 && NAME.is_match(name),
+        "invalid label key `{key}`: name must be at most 63 alphanumeric characters, `-`, `_` or `.`, starting and ending with an alphanumeric character"
+    );
+    Ok(())
+}
+
+fn validate_value(value: &str) -> anyhow::Result<()> {
+    ensure!(
+        value.is_empty() || (value.len() <= 63 && NAME.is_match(value)),
+        "invalid label value `{value}`: must be empty or at most 63 alphanumeric characters, `-`, `_` or `.`, starting and ending with an alphanumeric character"
+    );
+    Ok(())
+}
+
+static NAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([A-Za-z0-9][-A-Za-z0-9_.]*)?[A-Za-z0-9]$").unwrap());
+
 struct Parser<'s> {
-    lexer: Lexer<'s>,
+    lexer: Peekable<Lexer<'s>>,
 }
 
 impl Parser<'_> {
+    fn next(&mut self) -> anyhow::Result<Option<Token>> {
+        self.lexer.next().transpose()
+    }
+
+    /// The value of `=`, `==` and `!=` may be empty, as in `env=`.
     fn op_one(
         &mut self,
         operator: &str,
         base: Str,
         f: impl FnOnce(Str, Str) -> Selector,
     ) -> anyhow::Result<Selector> {
-        if let Some(Token::Ident(value)) = self.lexer.next().transpose()? {
-            Ok(f(base, value))
-        } else {
-            bail!("expected identifier after operator `{operator}`");
+        let value = match self.lexer.peek() {
+            None | Some(Ok(Token::Comma)) => Str::default(),
+            _ => match self.next()? {
+                Some(Token::Ident(value)) => value,
+                _ => bail!("expected value after operator `{operator}`"),
+            },
+        };
+
+        validate_value(&value)?;
+        Ok(f(base, value))
+    }
+
+    fn op_int(
+        &mut self,
+        operator: &str,
+        base: Str,
+        f: impl FnOnce(Str, i64) -> Selector,
+    ) -> anyhow::Result<Selector> {
+        match self.next()? {
+            Some(Token::Ident(value)) => match value.parse::<i64>() {
+                Ok(value) => Ok(f(base, value)),
+                Err(_) => bail!("expected an integer after operator `{operator}`, got `{value}`"),
+            },
+            _ => bail!("expected an integer after operator `{operator}`"),
         }
     }
 
@@ -71,55 +144,79 @@ impl Parser<'_> {
         base: Str,
         f: impl FnOnce(Str, IndexSet<Str>) -> Selector,
     ) -> anyhow::Result<Selector> {
-        let Some(Token::LeftParen) = self.lexer.next().transpose()? else {
+        let Some(Token::LeftParen) = self.next()? else {
             bail!("expected `(` after operator `{operator}`");
         };
 
+        if let Some(Ok(Token::RightParen)) = self.lexer.peek() {
+            bail!("values of operator `{operator}` can't be empty");
+        }
+
+        // Values may be empty, as in `env in (prod,)`.
         let mut values = IndexSet::new();
         loop {
-            match self.lexer.next().transpose()? {
-                Some(Token::Ident(value)) => {
-                    values.insert(value);
-                    match self.lexer.next().transpose()? {
-                        Some(Token::Comma) => continue,
-                        Some(Token::RightParen) => break,
-                        _ => bail!("expected `,` or `)` after value"),
-                    }
-                }
-                _ => bail!("expected identifier in set"),
+            let value = match self.lexer.peek() {
+                Some(Ok(Token::Comma | Token::RightParen)) => Str::default(),
+                _ => match self.next()? {
+                    Some(Token::Ident(value)) => value,
+                    _ => bail!("expected value in set of operator `{operator}`"),
+                },
+            };
+
+            validate_value(&value)?;
+            values.insert(value);
+
+            match self.next()? {
+                Some(Token::Comma) => continue,
+                Some(Token::RightParen) => break,
+                _ => bail!("expected `,` or `)` after value"),
             }
         }
 
         Ok(f(base, values))
     }
 
+    fn key(&mut self) -> anyhow::Result<Str> {
+        match self.next()? {
+            Some(Token::Ident(key)) => {
+                validate_key(&key)?;
+                Ok(key)
+            }
+            Some(token) => bail!("expected label key, got `{token}`"),
+            None => bail!("expected label key"),
+        }
+    }
+
     fn parse(&mut self) -> anyhow::Result<Selector> {
         let mut selectors = Vec::new();
-        loop {
-            let key = match self.lexer.next().transpose()? {
-                Some(Token::Ident(key)) => key,
-                Some(token) => bail!("unexpected token `{token:?}` at start of selector"),
-                None => break,
-            };
-
-            let selector = match self.lexer.next() {
-                Some(token) => match token? {
-                    Token::Ident(_) => bail!("unexpected identifier after selector key"),
-                    Token::Equal => self.op_one("=", key, Selector::Equality)?,
-                    Token::NotEqual => self.op_one("!=", key, Selector::Inequality)?,
-                    Token::In => self.op_many("in", key, Selector::SetInclusion)?,
-                    Token::NotIn => self.op_many("notin", key, Selector::SetExclusion)?,
-                    Token::Comma => bail!("unexpected `,` after selector key"),
-                    Token::LeftParen => bail!("unexpected `(` after selector key"),
-                    Token::RightParen => bail!("unexpected `)` after selector key"),
-                },
-                None => Selector::Existence(key),
+        while self.lexer.peek().is_some() {
+            let selector = if let Some(Ok(Token::Not)) = self.lexer.peek() {
+                self.next()?;
+                Selector::NonExistence(self.key()?)
+            } else {
+                let key = self.key()?;
+                match self.lexer.peek() {
+                    None | Some(Ok(Token::Comma)) => Selector::Existence(key),
+                    _ => match self.next()?.expect("peeked") {
+                        Token::Equal => self.op_one("=", key, Selector::Equality)?,
+                        Token::DoubleEqual => self.op_one("==", key, Selector::Equality)?,
+                        Token::NotEqual => self.op_one("!=", key, Selector::Inequality)?,
+                        Token::In => self.op_many("in", key, Selector::SetInclusion)?,
+                        Token::NotIn => self.op_many("notin", key, Selector::SetExclusion)?,
+                        Token::GreaterThan => self.op_int(">", key, Selector::GreaterThan)?,
+                        Token::LessThan => self.op_int("<", key, Selector::LessThan)?,
+                        token => bail!("unexpected `{token}` after label key"),
+                    },
+                }
             };
             selectors.push(selector);
 
-            match self.lexer.next().transpose()? {
+            match self.next()? {
+                Some(Token::Comma) if self.lexer.peek().is_none() => {
+                    bail!("expected selector after `,`")
+                }
                 Some(Token::Comma) => continue,
-                Some(token) => bail!("unexpected token `{token:?}` after selector"),
+                Some(token) => bail!("unexpected `{token}` after selector"),
                 None => break,
             }
         }
@@ -137,10 +234,12 @@ impl FromStr for Selector {
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
         let mut parser = Parser {
-            lexer: Lexer { input },
+            lexer: Lexer { input }.peekable(),
         };
 
-        parser.parse()
+        parser
+            .parse()
+            .with_context(|| format!("parsing selector `{input}`"))
     }
 }
 
@@ -164,6 +263,9 @@ impl fmt::Display for Selector {
                 )
             }
             Selector::Existence(key) => write!(f, "{key}"),
+            Selector::NonExistence(key) => write!(f, "!{key}"),
+            Selector::GreaterThan(key, value) => write!(f, "{key}>{value}"),
+            Selector::LessThan(key, value) => write!(f, "{key}<{value}"),
             Selector::All(selectors) => {
                 write!(
                     f,
@@ -188,13 +290,110 @@ impl Serialize for Selector {
     }
 }
 
+/// The structured form of a selector, as in a `Deployment`'s `spec.selector`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct StructuredSelector {
+    #[serde(default)]
+    match_labels: IndexMap<Str, Str>,
+    #[serde(default)]
+    match_expressions: Vec<MatchExpression>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MatchExpression {
+    key: Str,
+    operator: Operator,
+    #[serde(default)]
+    values: Vec<Str>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+enum Operator {
+    In,
+    NotIn,
+    Exists,
+    DoesNotExist,
+}
+
+impl TryFrom<StructuredSelector> for Selector {
+    type Error = anyhow::Error;
+
+    fn try_from(structured: StructuredSelector) -> Result<Self, Self::Error> {
+        let mut selectors = Vec::new();
+        for (key, value) in structured.match_labels {
+            validate_key(&key)?;
+            validate_value(&value)?;
+            selectors.push(Selector::Equality(key, value));
+        }
+
+        for MatchExpression {
+            key,
+            operator,
+            values,
+        } in structured.match_expressions
+        {
+            validate_key(&key)?;
+            for value in &values {
+                validate_value(value)?;
+            }
+
+            let selector = match operator {
+                Operator::In | Operator::NotIn => {
+                    ensure!(
+                        !values.is_empty(),
+                        "values of operator `{operator:?}` for key `{key}` can't be empty"
+                    );
+                    let values = values.into_iter().collect();
+                    match operator {
+                        Operator::In => Selector::SetInclusion(key, values),
+                        _ => Selector::SetExclusion(key, values),
+                    }
+                }
+                Operator::Exists | Operator::DoesNotExist => {
+                    ensure!(
+                        values.is_empty(),
+                        "values of operator `{operator:?}` for key `{key}` must be empty"
+                    );
+                    match operator {
+                        Operator::Exists => Selector::Existence(key),
+                        _ => Selector::NonExistence(key),
+                    }
+                }
+            };
+            selectors.push(selector);
+        }
+
+        if selectors.len() == 1 {
+            Ok(selectors.into_iter().next().unwrap())
+        } else {
+            Ok(Selector::All(selectors))
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for Selector {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let s = Str::deserialize(deserializer)?;
-        Selector::from_str(&s).map_err(serde::de::Error::custom)
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            String(Str),
+            Structured(json::Value),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::String(s) => {
+                Selector::from_str(&s).map_err(|err| serde::de::Error::custom(format!("{err:#}")))
+            }
+            Repr::Structured(value) => json::from_value::<StructuredSelector>(value)
+                .map_err(anyhow::Error::from)
+                .and_then(Selector::try_from)
+                .map_err(|err| serde::de::Error::custom(format!("{err:#}"))),
+        }
     }
 }
 
@@ -206,8 +405,12 @@ struct Lexer<'s> {
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum Token {
     Ident(Str),
+    Not,
     Equal,
+    DoubleEqual,
     NotEqual,
+    GreaterThan,
+    LessThan,
     In,
     NotIn,
     Comma,
@@ -215,48 +418,165 @@ enum Token {
     RightParen,
 }
 
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "{ident}"),
+            Token::Not => write!(f, "!"),
+            Token::Equal => write!(f, "="),
+            Token::DoubleEqual => write!(f, "=="),
+            Token::NotEqual => write!(f, "!="),
+            Token::GreaterThan => write!(f, ">"),
+            Token::LessThan => write!(f, "<"),
+            Token::In => write!(f, "in"),
+            Token::NotIn => write!(f, "notin"),
+            Token::Comma => write!(f, ","),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+        }
+    }
+}
+
 impl Iterator for Lexer<'_> {
     type Item = anyhow::Result<Token>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.input = self.input.trim_start();
-        let c = self.input.chars().next()?;
+        self.input.chars().next()?;
 
-        const PREFIXES: &[(&str, Token)] = &[
-            ("notin ", Token::NotIn),
-            ("in ", Token::In),
-            ("==", Token::Equal),
+        // Longer symbols come first so that e.g. `!=` isn't lexed as `!`.
+        const SYMBOLS: &[(&str, Token)] = &[
+            ("==", Token::DoubleEqual),
             ("!=", Token::NotEqual),
+            ("!", Token::Not),
+            ("=", Token::Equal),
+            (">", Token::GreaterThan),
+            ("<", Token::LessThan),
             (",", Token::Comma),
             ("(", Token::LeftParen),
             (")", Token::RightParen),
-            ("=", Token::Equal),
         ];
 
-        for &(prefix, ref token) in PREFIXES {
-            if self.input.starts_with(prefix) {
-                self.input = &self.input[prefix.len()..];
+        for &(symbol, ref token) in SYMBOLS {
+            if let Some(rest) = self.input.strip_prefix(symbol) {
+                self.input = rest;
                 return Some(Ok(token.clone()));
             }
         }
 
-        // Identifiers must start with an alphabetic character.
-        if !c.is_alphabetic() {
-            return Some(Err(anyhow::anyhow!("unexpected character '{c}'",)));
-        }
-
         let end = self
             .input
-            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '!' | ',' | '(' | ')'))
+            .find(|c: char| {
+                c.is_whitespace() || matches!(c, '=' | '!' | ',' | '(' | ')' | '>' | '<')
+            })
             .unwrap_or(self.input.len());
         let (ident, rest) = self.input.split_at(end);
         self.input = rest;
-        Some(Ok(Token::Ident(ident.trim().into())))
+        Some(Ok(match ident {
+            "in" => Token::In,
+            "notin" => Token::NotIn,
+            _ => Token::Ident(ident.into()),
+        }))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use indexmap::IndexMap;
+
+    use super::{Selector, StringMap};
+
+    struct Labels(IndexMap<&'static str, &'static str>);
+
+    impl StringMap for Labels {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).copied()
+        }
+
+        fn has(&self, key: &str) -> bool {
+            self.0.contains_key(key)
+        }
+    }
+
+    #[test]
+    fn matches() {
+        let labels = Labels(IndexMap::from([("app", "nginx"), ("tier", "3")]));
+        let cases = [
+            ("app=nginx", true),
+            ("app!=nginx", false),
+            ("env!=prod", true),
+            ("env notin (prod)", true),
+            ("app notin (nginx)", false),
+            ("env in (prod)", false),
+            ("!env", true),
+            ("!app", false),
+            ("tier>2", true),
+            ("tier<2", false),
+            ("app>2", false),
+            ("app,!env,env=", false),
+            ("", true),
+        ];
+
+        for (selector, expected) in cases {
+            let selector = selector.parse::<Selector>().unwrap();
+            assert_eq!(selector.matches(Some(&labels)), expected, "{selector}");
+        }
+
+        // Resources without labels behave as if they had none.
+        assert!(
+            "env!=prod"
+                .parse::<Selector>()
+                .unwrap()
+                .matches(None::<&Labels>)
+        );
+        assert!(!"env".parse::<Selector>().unwrap().matches(None::<&Labels>));
+    }
+
+    #[test]
+    fn invalid() {
+        let selectors = [
+            "app in ()",
+            "-app=nginx",
+            "app=nginx-",
+            "Example.com/app=nginx",
+            "app=a b",
+            "tier>high",
+            "!",
+            "app=nginx,",
+            "app in (a",
+        ];
+
+        for selector in selectors {
+            assert!(
+                selector.parse::<Selector>().is_err(),
+                "`{selector}` should be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn structured() {
+        let selector = json::from_value::<Selector>(json::json!({
+            "matchLabels": { "app": "nginx" },
+            "matchExpressions": [
+                { "key": "env", "operator": "In", "values": ["prod", "staging"] },
+                { "key": "canary", "operator": "DoesNotExist" },
+            ],
+        }))
+        .unwrap();
+        assert_eq!(
+            selector.to_string(),
+            "app=nginx,env in (prod, staging),!canary"
+        );
+
+        assert!(
+            json::from_value::<Selector>(json::json!({
+                "matchExpressions": [{ "key": "env", "operator": "Exists", "values": ["prod"] }],
+            }))
+            .is_err()
+        );
+    }
+
     #[test]
     fn serde() {
         let selectors = [
@@ -267,6 +587,9 @@ mod tests {
             "app!=nginx",
             "app=nginx,env=prod",
             "app!=nginx,env!=prod",
+            "!app",
+            "tier>2,tier<5",
+            "app.kubernetes.io/name=nginx,env=",
         ];
 
         for selector in selectors {
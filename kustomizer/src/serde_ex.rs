@@ -7,7 +7,10 @@ pub mod regex {
     where
         S: Serializer,
     {
-        serializer.collect_str(re)
+        // The anchors are implied when deserializing, so leave them out to round-trip cleanly.
+        let re = re.as_str();
+        let re = re.strip_prefix('^').unwrap_or(re);
+        serializer.collect_str(re.strip_suffix('$').unwrap_or(re))
     }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<Regex, D::Error>
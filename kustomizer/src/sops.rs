@@ -0,0 +1,839 @@
+//! In-memory decryption of files encrypted with [SOPS](https://github.com/getsops/sops).
+//!
+//! Only the data key of a file is encrypted to its recipients, the values are encrypted with
+//! AES-256-GCM using that key and the path to the value as additional data. The data key is
+//! decrypted with the age identities from `SOPS_AGE_KEY`, `SOPS_AGE_KEY_FILE` or the default sops
+//! key file, falling back to `gpg` (or `SOPS_GPG_EXEC`) for PGP recipients.
+//!
+//! Decrypted values never appear in errors or logs, and `Plaintext` doesn't print its contents.
+
+use std::{
+    fmt,
+    io::{Read as _, Write as _},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::LazyLock,
+};
+
+use aes_gcm::{
+    AesGcm, KeyInit as _,
+    aead::{Aead as _, Payload, consts::U32},
+    aes::Aes256,
+};
+use anyhow::{Context as _, anyhow, bail, ensure};
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use regex::Regex;
+use serde::Deserialize;
+use sha2::{Digest as _, Sha512};
+
+use crate::{PathExt as _, yaml};
+
+/// The key under which sops stores its metadata.
+const METADATA_KEY: &str = "sops";
+
+/// AES-256-GCM with the 32 byte nonces sops uses.
+type Cipher = AesGcm<Aes256, U32>;
+
+/// The formats sops stores encrypted files in, determined by the file extension as sops does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Yaml,
+    Json,
+    Dotenv,
+    /// Any other file, stored as a JSON document with the contents under `data`.
+    Binary,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Self::Yaml,
+            Some("json") => Self::Json,
+            Some("env") => Self::Dotenv,
+            _ => Self::Binary,
+        }
+    }
+}
+
+/// The decrypted contents of a file.
+pub(crate) enum Plaintext {
+    Tree(Format, json::Value),
+    Dotenv(Vec<(String, String)>),
+    Binary(Vec<u8>),
+}
+
+impl fmt::Debug for Plaintext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Plaintext(<redacted>)")
+    }
+}
+
+impl Plaintext {
+    /// The decrypted file, in the format it was stored in.
+    pub fn into_bytes(self) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            Plaintext::Tree(Format::Json, tree) => {
+                let mut bytes = json::to_vec_pretty(&tree)?;
+                bytes.push(b'\n');
+                bytes
+            }
+            Plaintext::Tree(_, tree) => yaml::to_string(&tree)?.into_bytes(),
+            Plaintext::Dotenv(entries) => entries
+                .into_iter()
+                .map(|(key, value)| format!("{key}={}\n", value.replace('\n', "\\n")))
+                .collect::<String>()
+                .into_bytes(),
+            Plaintext::Binary(bytes) => bytes,
+        })
+    }
+
+    /// The decrypted key value pairs of a dotenv file, or of the top-level keys of a YAML or JSON
+    /// document with scalar values.
+    pub fn into_pairs(self) -> anyhow::Result<Vec<(String, String)>> {
+        match self {
+            Plaintext::Dotenv(entries) => Ok(entries),
+            Plaintext::Tree(_, json::Value::Object(map)) => map
+                .into_iter()
+                .map(|(key, value)| match value {
+                    json::Value::String(s) => Ok((key, s)),
+                    json::Value::Number(n) => Ok((key, n.to_string())),
+                    json::Value::Bool(b) => Ok((key, b.to_string())),
+                    json::Value::Null => Ok((key, String::new())),
+                    _ => bail!("value of `{key}` is not a scalar"),
+                })
+                .collect(),
+            Plaintext::Tree(..) => bail!("expected a mapping of keys to values"),
+            Plaintext::Binary(_) => bail!("binary files can't be used as key value pairs"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    #[serde(default)]
+    age: Vec<AgeRecipient>,
+    #[serde(default)]
+    pgp: Vec<PgpRecipient>,
+    mac: String,
+    lastmodified: String,
+    #[serde(default)]
+    mac_only_encrypted: bool,
+}
+
+#[derive(Deserialize)]
+struct AgeRecipient {
+    recipient: String,
+    enc: String,
+}
+
+#[derive(Deserialize)]
+struct PgpRecipient {
+    fp: String,
+    enc: String,
+}
+
+/// Whether `content` is a file encrypted by sops, judging by the presence of its metadata.
+pub(crate) fn is_encrypted(format: Format, content: &[u8]) -> bool {
+    // Cheap check to avoid parsing every file.
+    if !content.windows(4).any(|window| window == b"sops") {
+        return false;
+    }
+
+    match format {
+        Format::Dotenv => content
+            .split(|&b| b == b'\n')
+            .any(|line| line.starts_with(b"sops_mac=")),
+        Format::Yaml | Format::Json | Format::Binary => {
+            yaml::from_slice::<json::Value>(content).is_ok_and(|tree| has_metadata(&tree))
+        }
+    }
+}
+
+/// Decrypts a file encrypted by sops and verifies its MAC. `path` is only used for errors.
+#[tracing::instrument(skip_all, fields(path = %path.pretty()))]
+pub(crate) fn decrypt(
+    path: &Path,
+    format: Format,
+    content: &[u8],
+    keys: &Keys,
+) -> anyhow::Result<Plaintext> {
+    let decrypt = || match format {
+        Format::Yaml | Format::Json | Format::Binary => {
+            let tree = decrypt_tree(yaml::from_slice(content)?, keys)?;
+            match format {
+                Format::Binary => match tree {
+                    json::Value::Object(mut map) => match map.shift_remove("data") {
+                        Some(json::Value::String(data)) => Ok(Plaintext::Binary(data.into_bytes())),
+                        _ => bail!("expected binary file to contain `data`"),
+                    },
+                    _ => bail!("expected binary file to contain `data`"),
+                },
+                _ => Ok(Plaintext::Tree(format, tree)),
+            }
+        }
+        Format::Dotenv => decrypt_dotenv(content, keys),
+    };
+
+    decrypt().with_context(|| format!("decrypting sops file {}", path.pretty()))
+}
+
+/// Decrypts a YAML or JSON document, returning it without the sops metadata.
+fn decrypt_tree(tree: json::Value, keys: &Keys) -> anyhow::Result<json::Value> {
+    ensure!(
+        tree.is_object(),
+        "expected a mapping at the root of the document"
+    );
+
+    let mut documents = [tree];
+    decrypt_documents(&mut documents, keys)?;
+    let [tree] = documents;
+    Ok(tree)
+}
+
+/// Whether a YAML or JSON document carries sops metadata.
+pub(crate) fn has_metadata(document: &json::Value) -> bool {
+    document
+        .get(METADATA_KEY)
+        .is_some_and(|metadata| metadata.get("mac").is_some())
+}
+
+/// Decrypts the documents of a file in place and removes their sops metadata. The documents of
+/// a multi-document YAML file share a data key and MAC, so they must be decrypted together.
+pub(crate) fn decrypt_documents(documents: &mut [json::Value], keys: &Keys) -> anyhow::Result<()> {
+    let mut metadata = None;
+    for document in documents.iter_mut() {
+        if let Some(map) = document.as_object_mut()
+            && let Some(md) = map.shift_remove(METADATA_KEY)
+        {
+            metadata.get_or_insert(md);
+        }
+    }
+
+    let metadata = metadata.context("missing sops metadata")?;
+    let metadata = json::from_value::<Metadata>(metadata).context("parsing sops metadata")?;
+    let key = data_key(&metadata, keys)?;
+
+    let mut mac = Mac::new(metadata.mac_only_encrypted);
+    let mut path = vec![];
+    for document in documents {
+        decrypt_value(&key, document, &mut path, &mut mac)?;
+    }
+    mac.verify(&key, &metadata)?;
+
+    Ok(())
+}
+
+fn decrypt_value(
+    key: &[u8],
+    value: &mut json::Value,
+    path: &mut Vec<String>,
+    mac: &mut Mac,
+) -> anyhow::Result<()> {
+    match value {
+        json::Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                path.push(k.clone());
+                decrypt_value(key, v, path, mac)?;
+                path.pop();
+            }
+        }
+        // Like sops, list indices are not part of the path.
+        json::Value::Array(items) => {
+            for item in items {
+                decrypt_value(key, item, path, mac)?;
+            }
+        }
+        json::Value::String(s) => match EncryptedValue::parse(s)? {
+            Some(encrypted) => {
+                let plaintext = encrypted.decrypt(key, &additional_data(path))?;
+                mac.update_encrypted(&plaintext);
+                *value = encrypted.ty.to_value(plaintext).with_context(|| {
+                    format!(
+                        "decrypted value at `{}` is not a valid {}",
+                        path.join("."),
+                        encrypted.ty
+                    )
+                })?;
+            }
+            None => mac.update_unencrypted(s.as_bytes()),
+        },
+        json::Value::Number(n) => mac.update_unencrypted(number_bytes(n).as_bytes()),
+        json::Value::Bool(b) => mac.update_unencrypted(bool_bytes(*b).as_bytes()),
+        json::Value::Null => {}
+    }
+
+    Ok(())
+}
+
+/// A boolean as sops's `ToBytes` encodes it, which is what the MAC covers.
+fn bool_bytes(b: bool) -> &'static str {
+    if b { "True" } else { "False" }
+}
+
+/// A number as sops's `ToBytes` encodes it. Floats are formatted with Go's
+/// `strconv.FormatFloat(f, 'f', -1, 64)`, the shortest representation without an exponent, which
+/// is also what Rust's `Display` for `f64` produces.
+fn number_bytes(n: &json::Number) -> String {
+    match n.as_f64() {
+        Some(f) if n.is_f64() => f.to_string(),
+        _ => n.to_string(),
+    }
+}
+
+/// Parses a boolean as Go's `strconv.ParseBool` does, which sops uses to decrypt booleans.
+fn parse_bool(s: &str) -> Option<bool> {
+    match s {
+        "1" | "t" | "T" | "true" | "TRUE" | "True" => Some(true),
+        "0" | "f" | "F" | "false" | "FALSE" | "False" => Some(false),
+        _ => None,
+    }
+}
+
+/// Decrypts a dotenv file, where the metadata is flattened into `sops_` prefixed keys.
+fn decrypt_dotenv(content: &[u8], keys: &Keys) -> anyhow::Result<Plaintext> {
+    let content = std::str::from_utf8(content).context("dotenv file is not valid utf-8")?;
+
+    let mut entries = vec![];
+    let mut metadata = json::Map::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .with_context(|| format!("line {}: expected `KEY=VALUE`", i + 1))?;
+        // Newlines are escaped by sops when writing dotenv files.
+        let value = value.replace("\\n", "\n");
+        match key.strip_prefix("sops_") {
+            Some(key) => unflatten_into(&mut metadata, key, value),
+            None => entries.push((key.to_string(), value)),
+        }
+    }
+
+    let metadata = json::from_value::<Metadata>(json::Value::Object(metadata))
+        .context("parsing sops metadata")?;
+    let key = data_key(&metadata, keys)?;
+
+    let mut mac = Mac::new(metadata.mac_only_encrypted);
+    for (k, value) in &mut entries {
+        match EncryptedValue::parse(value)? {
+            Some(encrypted) => {
+                let plaintext = encrypted.decrypt(&key, &format!("{k}:"))?;
+                mac.update_encrypted(&plaintext);
+                *value = String::from_utf8(plaintext)
+                    .map_err(|_| anyhow!("decrypted value of `{k}` is not valid utf-8"))?;
+            }
+            None => mac.update_unencrypted(value.as_bytes()),
+        }
+    }
+    mac.verify(&key, &metadata)?;
+
+    Ok(Plaintext::Dotenv(entries))
+}
+
+/// Inserts a flattened dotenv metadata key such as `age__list_0__map_enc` into `map`.
+fn unflatten_into(map: &mut json::Map<String, json::Value>, key: &str, value: String) {
+    let mut parts = key.split("__");
+    let first = parts.next().unwrap_or_default();
+    let mut slot = map.entry(first.to_string()).or_insert(json::Value::Null);
+    for part in parts {
+        if let Some(index) = part
+            .strip_prefix("list_")
+            .and_then(|i| i.parse::<usize>().ok())
+        {
+            if !slot.is_array() {
+                *slot = json::Value::Array(vec![]);
+            }
+            let items = slot.as_array_mut().unwrap();
+            if items.len() <= index {
+                items.resize(index + 1, json::Value::Null);
+            }
+            slot = &mut items[index];
+        } else {
+            let part = part.strip_prefix("map_").unwrap_or(part);
+            if !slot.is_object() {
+                *slot = json::Value::Object(Default::default());
+            }
+            slot = slot
+                .as_object_mut()
+                .unwrap()
+                .entry(part.to_string())
+                .or_insert(json::Value::Null);
+        }
+    }
+
+    // Metadata flags are stored as strings in dotenv files.
+    *slot = match value.as_str() {
+        "true" => json::Value::Bool(true),
+        "false" => json::Value::Bool(false),
+        _ => json::Value::String(value),
+    };
+}
+
+fn additional_data(path: &[String]) -> String {
+    format!("{}:", path.join(":"))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ValueType {
+    Str,
+    Int,
+    Float,
+    Bool,
+    Bytes,
+    Comment,
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueType::Str => write!(f, "str"),
+            ValueType::Int => write!(f, "int"),
+            ValueType::Float => write!(f, "float"),
+            ValueType::Bool => write!(f, "bool"),
+            ValueType::Bytes => write!(f, "bytes"),
+            ValueType::Comment => write!(f, "comment"),
+        }
+    }
+}
+
+impl ValueType {
+    fn to_value(self, plaintext: Vec<u8>) -> Option<json::Value> {
+        let s = String::from_utf8(plaintext).ok()?;
+        Some(match self {
+            ValueType::Str | ValueType::Bytes | ValueType::Comment => json::Value::String(s),
+            ValueType::Int => json::Value::Number(s.parse::<i64>().ok()?.into()),
+            ValueType::Float => json::Value::Number(json::Number::from_f64(s.parse().ok()?)?),
+            ValueType::Bool => json::Value::Bool(parse_bool(&s)?),
+        })
+    }
+}
+
+/// A value of the form `ENC[AES256_GCM,data:...,iv:...,tag:...,type:...]`.
+struct EncryptedValue {
+    data: Vec<u8>,
+    iv: Vec<u8>,
+    tag: Vec<u8>,
+    ty: ValueType,
+}
+
+impl EncryptedValue {
+    fn parse(s: &str) -> anyhow::Result<Option<Self>> {
+        static RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"^ENC\[AES256_GCM,data:(.*),iv:(.*),tag:(.*),type:(.*)\]$").unwrap()
+        });
+
+        let Some(captures) = RE.captures(s) else {
+            return Ok(None);
+        };
+
+        let decode = |i: usize| {
+            BASE64_STANDARD
+                .decode(&captures[i])
+                .context("invalid base64 in encrypted value")
+        };
+
+        let ty = match &captures[4] {
+            "str" => ValueType::Str,
+            "int" => ValueType::Int,
+            "float" => ValueType::Float,
+            "bool" => ValueType::Bool,
+            "bytes" => ValueType::Bytes,
+            "comment" => ValueType::Comment,
+            ty => bail!("unknown encrypted value type `{ty}`"),
+        };
+
+        Ok(Some(Self {
+            data: decode(1)?,
+            iv: decode(2)?,
+            tag: decode(3)?,
+            ty,
+        }))
+    }
+
+    fn decrypt(&self, key: &[u8], additional_data: &str) -> anyhow::Result<Vec<u8>> {
+        ensure!(self.iv.len() == 32, "invalid iv length in encrypted value");
+        let cipher = Cipher::new_from_slice(key).map_err(|_| anyhow!("invalid data key length"))?;
+        let msg = [self.data.as_slice(), self.tag.as_slice()].concat();
+        cipher
+            .decrypt(
+                self.iv.as_slice().into(),
+                Payload {
+                    msg: &msg,
+                    aad: additional_data.as_bytes(),
+                },
+            )
+            .map_err(|_| {
+                anyhow!(
+                    "failed to decrypt value at `{}`",
+                    additional_data.trim_end_matches(':').replace(':', ".")
+                )
+            })
+    }
+}
+
+/// The message authentication code over the values of a file, which detects tampering with
+/// both the encrypted and unencrypted values.
+struct Mac {
+    hasher: Sha512,
+    only_encrypted: bool,
+}
+
+impl Mac {
+    fn new(only_encrypted: bool) -> Self {
+        Self {
+            hasher: Sha512::new(),
+            only_encrypted,
+        }
+    }
+
+    fn update_encrypted(&mut self, plaintext: &[u8]) {
+        self.hasher.update(plaintext);
+    }
+
+    fn update_unencrypted(&mut self, value: &[u8]) {
+        if !self.only_encrypted {
+            self.hasher.update(value);
+        }
+    }
+
+    fn verify(self, key: &[u8], metadata: &Metadata) -> anyhow::Result<()> {
+        let expected = EncryptedValue::parse(&metadata.mac)?
+            .context("sops MAC is not encrypted")?
+            .decrypt(key, &metadata.lastmodified)
+            .context("decrypting sops MAC")?;
+
+        let actual = self
+            .hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<String>();
+
+        ensure!(
+            expected == actual.as_bytes(),
+            "MAC mismatch, the file has been modified since it was encrypted"
+        );
+
+        Ok(())
+    }
+}
+
+/// The keys to decrypt the data keys of files with.
+pub(crate) struct Keys {
+    age: Vec<Box<dyn age::Identity>>,
+    gpg: bool,
+}
+
+impl Keys {
+    /// The given age identities, in the format of an age key file, or the ones sops would use
+    /// otherwise, along with `gpg` for PGP recipients.
+    pub fn new(age_keys: Option<&str>) -> anyhow::Result<Self> {
+        let age = match age_keys {
+            Some(keys) => parse_age_identities(keys.as_bytes())
+                .context("parsing age identities from the build options")?,
+            None => age_identities()?,
+        };
+        Ok(Self { age, gpg: true })
+    }
+}
+
+/// Decrypts the data key of a file with the first recipient we have a key for.
+fn data_key(metadata: &Metadata, keys: &Keys) -> anyhow::Result<Vec<u8>> {
+    let mut errors = vec![];
+
+    if !metadata.age.is_empty() {
+        if keys.age.is_empty() {
+            errors.push("no age identities found".to_string());
+        }
+
+        for recipient in metadata.age.iter().filter(|_| !keys.age.is_empty()) {
+            match age_decrypt(&keys.age, &recipient.enc) {
+                Ok(key) => return Ok(key),
+                Err(err) => errors.push(format!("age recipient `{}`: {err}", recipient.recipient)),
+            }
+        }
+    }
+
+    for recipient in metadata.pgp.iter().filter(|_| keys.gpg) {
+        match gpg_decrypt(&recipient.enc) {
+            Ok(key) => return Ok(key),
+            Err(err) => errors.push(format!("pgp key `{}`: {err:#}", recipient.fp)),
+        }
+    }
+
+    if errors.is_empty() {
+        bail!("no age or pgp recipients to decrypt the data key with");
+    }
+
+    bail!("failed to decrypt the data key:\n  {}", errors.join("\n  "))
+}
+
+fn age_identities() -> anyhow::Result<Vec<Box<dyn age::Identity>>> {
+    let mut identities = vec![];
+
+    if let Ok(keys) = std::env::var("SOPS_AGE_KEY") {
+        identities.extend(
+            parse_age_identities(keys.as_bytes())
+                .context("parsing age identities from `SOPS_AGE_KEY`")?,
+        );
+    }
+
+    let path = match std::env::var_os("SOPS_AGE_KEY_FILE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => default_age_key_file().filter(|path| path.exists()),
+    };
+
+    if let Some(path) = path {
+        let file = std::fs::File::open(&path)
+            .with_context(|| format!("reading age identities from {}", path.pretty()))?;
+        identities.extend(
+            parse_age_identities(std::io::BufReader::new(file))
+                .with_context(|| format!("parsing age identities from {}", path.pretty()))?,
+        );
+    }
+
+    Ok(identities)
+}
+
+fn parse_age_identities(
+    keys: impl std::io::BufRead,
+) -> std::io::Result<Vec<Box<dyn age::Identity>>> {
+    age::IdentityFile::from_buffer(keys)
+        .and_then(|file| file.into_identities().map_err(std::io::Error::other))
+}
+
+/// The key file sops reads by default, `$XDG_CONFIG_HOME/sops/age/keys.txt`.
+fn default_age_key_file() -> Option<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(config) => PathBuf::from(config),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("sops/age/keys.txt"))
+}
+
+fn age_decrypt(identities: &[Box<dyn age::Identity>], enc: &str) -> anyhow::Result<Vec<u8>> {
+    let decryptor = age::Decryptor::new(age::armor::ArmoredReader::new(enc.as_bytes()))?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))?;
+    let mut key = vec![];
+    reader.read_to_end(&mut key)?;
+    Ok(key)
+}
+
+fn gpg_decrypt(enc: &str) -> anyhow::Result<Vec<u8>> {
+    let gpg = std::env::var_os("SOPS_GPG_EXEC").unwrap_or_else(|| "gpg".into());
+    let mut child = Command::new(&gpg)
+        .args(["--quiet", "--batch", "--no-tty", "--decrypt"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("running `{}`", gpg.to_string_lossy()))?;
+
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(enc.as_bytes())?;
+
+    let output = child.wait_with_output()?;
+    ensure!(
+        output.status.success(),
+        "`{}` failed: {}",
+        gpg.to_string_lossy(),
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Keys {
+        let identities =
+            age::IdentityFile::from_buffer(include_bytes!("sops/testdata/keys.txt").as_slice())
+                .unwrap()
+                .into_identities()
+                .unwrap();
+        Keys {
+            age: identities,
+            gpg: false,
+        }
+    }
+
+    fn decrypt(path: &str, content: &[u8]) -> anyhow::Result<Plaintext> {
+        let path = Path::new(path);
+        let format = Format::from_path(path);
+        assert!(
+            is_encrypted(format, content),
+            "{path:?} should be detected as encrypted"
+        );
+        super::decrypt(path, format, content, &keys())
+    }
+
+    #[test]
+    fn tree() -> anyhow::Result<()> {
+        let expected = json::json!({
+            "hello": "Welcome to SOPS! Edit this file as you please!",
+            "example_key": "example_value",
+            "example_array": ["example_value1", "example_value2"],
+            "example_number": 1234.56789,
+            "example_booleans": [true, false],
+        });
+
+        for (path, content) in [
+            (
+                "example.yaml",
+                include_bytes!("sops/testdata/example.yaml").as_slice(),
+            ),
+            (
+                "example.json",
+                include_bytes!("sops/testdata/example.json").as_slice(),
+            ),
+        ] {
+            let Plaintext::Tree(_, tree) = decrypt(path, content)? else {
+                panic!("expected a tree for {path}");
+            };
+            assert_eq!(tree, expected, "{path}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn unencrypted_values() -> anyhow::Result<()> {
+        let Plaintext::Tree(_, tree) = decrypt(
+            "unencrypted_suffix.yaml",
+            include_bytes!("sops/testdata/unencrypted_suffix.yaml"),
+        )?
+        else {
+            panic!("expected a tree");
+        };
+        assert_eq!(
+            tree,
+            json::json!({
+                "a": {
+                    "b_unencrypted": { "a": "aaa", "b": "aaa", "c": [123, 456] },
+                    "c_encrypted": "xxx",
+                },
+                "unencrypted_not": "xxx",
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn go_encoding() {
+        assert_eq!(bool_bytes(true), "True");
+        assert_eq!(bool_bytes(false), "False");
+        for (number, bytes) in [
+            ("1234.56789", "1234.56789"),
+            ("1.0", "1"),
+            ("1e21", "1000000000000000000000"),
+            ("0.000001", "0.000001"),
+            ("-42", "-42"),
+        ] {
+            let number = json::from_str::<json::Number>(number).unwrap();
+            assert_eq!(number_bytes(&number), bytes);
+        }
+
+        let value = |s: &str| ValueType::Bool.to_value(s.as_bytes().to_vec());
+        assert_eq!(value("True"), Some(json::Value::Bool(true)));
+        assert_eq!(value("FALSE"), Some(json::Value::Bool(false)));
+        assert_eq!(value("yes"), None);
+    }
+
+    #[test]
+    fn dotenv() -> anyhow::Result<()> {
+        let pairs = decrypt(
+            "credentials.env",
+            include_bytes!("sops/testdata/credentials.env"),
+        )?
+        .into_pairs()?;
+        assert_eq!(
+            pairs,
+            [
+                ("USERNAME".to_string(), "admin".to_string()),
+                ("PASSWORD".to_string(), "p@ss=word".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn binary() -> anyhow::Result<()> {
+        let bytes =
+            decrypt("token.txt", include_bytes!("sops/testdata/token.txt"))?.into_bytes()?;
+        assert_eq!(bytes, b"binary token contents\n");
+        Ok(())
+    }
+
+    #[test]
+    fn documents() -> anyhow::Result<()> {
+        let content = include_bytes!("sops/testdata/secrets.yaml");
+        let mut documents = yaml::from_reader_multi::<json::Value>(content.as_slice())?;
+        assert!(documents.iter().all(has_metadata));
+
+        decrypt_documents(&mut documents, &keys())?;
+        assert_eq!(
+            documents.as_ref(),
+            [
+                json::json!({
+                    "apiVersion": "v1",
+                    "kind": "Secret",
+                    "metadata": { "name": "db" },
+                    "type": "Opaque",
+                    "stringData": { "password": "hunter2" },
+                }),
+                json::json!({
+                    "apiVersion": "v1",
+                    "kind": "Secret",
+                    "metadata": { "name": "api" },
+                    "type": "Opaque",
+                    "data": { "token": "c2VjcmV0LXRva2Vu" },
+                }),
+            ]
+        );
+
+        // The MAC covers the unencrypted values of every document.
+        let content = std::str::from_utf8(content)?.replace("name: \"api\"", "name: \"other\"");
+        let mut documents = yaml::from_reader_multi::<json::Value>(content.as_bytes())?;
+        let err = decrypt_documents(&mut documents, &keys()).unwrap_err();
+        assert!(format!("{err:#}").contains("MAC mismatch"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn not_encrypted() {
+        assert!(!is_encrypted(Format::Yaml, b"sops: is just a key\n"));
+        assert!(!is_encrypted(Format::Dotenv, b"SOPS=1\nsops_version=3\n"));
+        assert!(!is_encrypted(Format::Binary, b"plain text"));
+    }
+
+    #[test]
+    fn tampered() {
+        let content = std::str::from_utf8(include_bytes!("sops/testdata/unencrypted_suffix.yaml"))
+            .unwrap()
+            .replace("- 456", "- 457");
+        let err = decrypt("unencrypted_suffix.yaml", content.as_bytes()).unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("MAC mismatch"), "{err}");
+        assert!(!err.contains("xxx"), "{err}");
+    }
+
+    #[test]
+    fn missing_identity() {
+        let content = include_bytes!("sops/testdata/example.yaml");
+        let keys = Keys {
+            age: vec![],
+            gpg: false,
+        };
+        let err =
+            super::decrypt(Path::new("example.yaml"), Format::Yaml, content, &keys).unwrap_err();
+        assert!(format!("{err:#}").contains("no age identities found"));
+    }
+}
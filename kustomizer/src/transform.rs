@@ -1,4 +1,5 @@
 mod annotation;
+mod checksum;
 mod cleanup;
 mod function;
 mod image;
@@ -10,8 +11,9 @@ mod refs;
 mod replica;
 
 pub use self::annotation::AnnotationTransformer;
+pub use self::checksum::ChecksumTransformer;
 pub use self::cleanup::CleanupTransformer;
-pub use self::image::ImageTagTransformer;
+pub use self::image::{ImageLockTransformer, ImageRegistryTransformer, ImageTagTransformer};
 pub use self::label::LabelTransformer;
 pub use self::name::NameTransformer;
 pub use self::namespace::NamespaceTransformer;
@@ -0,0 +1,105 @@
+use anyhow::{anyhow, bail};
+use indexmap::{IndexMap, map::Entry};
+use json::Value;
+
+use crate::{
+    fieldspec,
+    manifest::Str,
+    resmap::ResourceMap,
+    resource::{Object, RefSpecs, ResId, Resource},
+};
+
+use super::{Transformer, refs::reference_matches};
+
+/// Annotates pod templates with `checksum/<name>: <hash>` for every ConfigMap and Secret they refer
+/// to, so workloads are rolled out when the data changes even if the names aren't suffixed with a hash.
+pub struct ChecksumTransformer<'a> {
+    ref_specs: &'a RefSpecs,
+}
+
+impl<'a> ChecksumTransformer<'a> {
+    pub fn new(ref_specs: &'a RefSpecs) -> Self {
+        Self { ref_specs }
+    }
+}
+
+impl Transformer for ChecksumTransformer<'_> {
+    #[tracing::instrument(skip_all, name = "checksum_transform")]
+    async fn transform(&mut self, resources: &mut ResourceMap) -> anyhow::Result<()> {
+        let mut referees = vec![];
+        for resource in resources.iter() {
+            let gvk = &resource.id().gvk;
+            if gvk.group.is_empty() && matches!(gvk.kind.as_str(), "ConfigMap" | "Secret") {
+                referees.push((resource.id().clone(), resource.shorthash()?));
+            }
+        }
+
+        if referees.is_empty() {
+            return Ok(());
+        }
+
+        let field_specs = &fieldspec::Builtin::load().template_annotations;
+
+        for resource in resources.iter_mut() {
+            let mut checksums = IndexMap::<Str, (&ResId, &Str)>::new();
+            for (res_id, hash) in &referees {
+                if !self.refers_to(resource, res_id)? {
+                    continue;
+                }
+
+                match checksums.entry(res_id.name.clone()) {
+                    Entry::Occupied(entry) => bail!(
+                        "`{}` refers to both {} and {}, which would share the annotation `checksum/{}`",
+                        resource.id(),
+                        entry.get().0,
+                        res_id,
+                        res_id.name
+                    ),
+                    Entry::Vacant(entry) => {
+                        entry.insert((res_id, hash));
+                    }
+                }
+            }
+
+            if checksums.is_empty() {
+                continue;
+            }
+
+            field_specs.apply::<Object>(resource, |annotations| {
+                for (name, (_, hash)) in &checksums {
+                    annotations.insert(format!("checksum/{name}"), Value::String(hash.to_string()));
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ChecksumTransformer<'_> {
+    /// Whether any field of `resource` known to refer to resources of this type refers to `res_id`.
+    fn refers_to(&self, resource: &mut Resource, res_id: &ResId) -> anyhow::Result<bool> {
+        // References by name alone are to resources in the same namespace.
+        let same_namespace = resource.namespace().map_or("default", |ns| ns.as_str())
+            == res_id.namespace.as_deref().unwrap_or("default");
+
+        let mut found = false;
+        for referrer_spec in self.ref_specs.referrers(&res_id.gvk) {
+            referrer_spec.apply::<Value>(resource, &mut |reference_value| {
+                found |= match reference_value {
+                    Value::String(name) => same_namespace && name == &res_id.name,
+                    Value::Object(ref_obj) => reference_matches(ref_obj, res_id)?,
+                    _ => {
+                        return Err(anyhow!(
+                            "Unexpected reference value type: expected string or object"
+                        ));
+                    }
+                };
+                Ok(())
+            })?;
+        }
+
+        Ok(found)
+    }
+}
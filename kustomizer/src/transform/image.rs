@@ -1,8 +1,13 @@
+use anyhow::{Context, bail, ensure};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    manifest::{ImageTag, TypeMeta, apiversion, kind},
+    PathExt as _, PathId,
+    image::{ImageGlob, ImageRef},
+    manifest::{ImageTag, Str, TypeMeta, apiversion, kind},
     resource::Metadata,
+    yaml,
 };
 
 use super::{ResourceMap, Transformer};
@@ -14,6 +19,8 @@ pub struct ImageTagTransformer {
     type_meta: TypeMeta<apiversion::Builtin, kind::ImageTagTransformer>,
     metadata: Metadata,
     image_tag: ImageTag,
+    #[serde(skip)]
+    matched: bool,
 }
 
 impl From<ImageTag> for ImageTagTransformer {
@@ -25,10 +32,18 @@ impl From<ImageTag> for ImageTagTransformer {
             },
             metadata: Metadata::default(),
             image_tag,
+            matched: false,
         }
     }
 }
 
+impl ImageTagTransformer {
+    /// Whether any image matched the name of the image tag during the last transform.
+    pub fn matched(&self) -> bool {
+        self.matched
+    }
+}
+
 impl Transformer for ImageTagTransformer {
     #[tracing::instrument(
         skip_all,
@@ -41,34 +56,268 @@ impl Transformer for ImageTagTransformer {
     )]
     async fn transform(&mut self, resources: &mut ResourceMap) -> anyhow::Result<()> {
         let field_specs = &crate::fieldspec::Builtin::load().images;
+        let image_tag = &self.image_tag;
+        let matched = &mut self.matched;
+
+        if !image_tag.tag_suffix.is_empty()
+            && (!image_tag.new_tag.is_empty() || !image_tag.digest.is_empty())
+        {
+            bail!(
+                "image `{}`: `tagSuffix` can't be combined with `newTag` or `digest`",
+                image_tag.name
+            );
+        }
 
         for resource in resources.iter_mut() {
             field_specs.apply::<String>(resource, |image_ref| {
-                let image_name = image_ref
-                    .split(':')
-                    .next()
-                    .ok_or_else(|| anyhow::anyhow!("invalid image: `{image_ref}`"))?;
-                if *image_name == self.image_tag.name {
-                    let new_name = if self.image_tag.new_name.is_empty() {
-                        self.image_tag.name.clone()
-                    } else {
-                        self.image_tag.new_name.clone()
-                    };
-
-                    // `digest` takes precedence over `new_tag`
-                    if !self.image_tag.digest.is_empty() {
-                        *image_ref = format!("{new_name}@{}", self.image_tag.digest);
-                        return Ok(());
-                    }
+                // References that aren't valid images (e.g. unrendered templates) never match.
+                let Ok(mut image) = image_ref.parse::<ImageRef>() else {
+                    return Ok(());
+                };
+                if !image.matches_name(&image_tag.name) {
+                    return Ok(());
+                }
+
+                *matched = true;
+
+                if !image_tag.new_name.is_empty() {
+                    let new_image = image_tag.new_name.parse::<ImageRef>().with_context(|| {
+                        format!("invalid `newName` for image `{}`", image_tag.name)
+                    })?;
+                    image.registry = new_image.registry;
+                    image.port = new_image.port;
+                    image.repository = new_image.repository;
+                }
 
-                    if !self.image_tag.new_tag.is_empty() {
-                        *image_ref = format!("{new_name}:{}", self.image_tag.new_tag);
+                // Overriding the tag or digest replaces both the original tag and digest.
+                match (image_tag.new_tag.is_empty(), image_tag.digest.is_empty()) {
+                    (false, false) => {
+                        image.tag = Some(image_tag.new_tag.clone());
+                        image.digest = Some(image_tag.digest.clone());
                     }
+                    (false, true) => {
+                        image.tag = Some(image_tag.new_tag.clone());
+                        image.digest = None;
+                    }
+                    (true, false) => {
+                        image.tag = None;
+                        image.digest = Some(image_tag.digest.clone());
+                    }
+                    (true, true) if !image_tag.tag_suffix.is_empty() => {
+                        let mut tag = image.tag.take().unwrap_or_default();
+                        tag.push_str(&image_tag.tag_suffix);
+                        image.tag = Some(tag);
+                        image.digest = None;
+                    }
+                    (true, true) => {}
                 }
 
+                *image_ref = image.to_string();
                 Ok(())
             })?;
         }
         Ok(())
     }
 }
+
+/// Rewrites image names to mirrors and optionally restricts images to a set of registries.
+/// Rules match against the qualified image name (e.g. `docker.io/library/nginx` for `nginx`),
+/// the first matching rule applies and the tag and digest are preserved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageRegistryTransformer {
+    #[serde(flatten)]
+    type_meta: TypeMeta<apiversion::Builtin, kind::ImageRegistryTransformer>,
+    metadata: Metadata,
+    #[serde(default)]
+    mirrors: Vec<Mirror>,
+    /// If non-empty, the build fails on any image (after rewriting) that is not from one of
+    /// these registries. Entries without a `/` match the registry (with port), other entries
+    /// match the qualified name.
+    #[serde(default)]
+    allowed_registries: Vec<ImageGlob>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Mirror {
+    from: ImageGlob,
+    to: Str,
+}
+
+impl ImageRegistryTransformer {
+    fn rewrite(&self, image_ref: &mut String) -> anyhow::Result<()> {
+        let mut image = match image_ref.parse::<ImageRef>() {
+            Ok(image) => image,
+            // Without an allowlist, references that aren't valid images are left alone.
+            Err(_) if self.allowed_registries.is_empty() => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        let name = image.qualified_name();
+        if let Some(rewritten) = self
+            .mirrors
+            .iter()
+            .find_map(|mirror| mirror.from.rewrite(&name, &mirror.to))
+        {
+            let rewritten = rewritten
+                .parse::<ImageRef>()
+                .with_context(|| format!("rewriting image `{image_ref}` to `{rewritten}`"))?;
+            ensure!(
+                rewritten.tag.is_none() && rewritten.digest.is_none(),
+                "rewriting image `{image_ref}` to `{rewritten}`: mirror names must not have a tag or digest",
+            );
+            image.registry = rewritten.registry;
+            image.port = rewritten.port;
+            image.repository = rewritten.repository;
+            *image_ref = image.to_string();
+        }
+
+        if !self.allowed_registries.is_empty() {
+            let registry = image.qualified_registry();
+            let name = image.qualified_name();
+            if !self.allowed_registries.iter().any(|allowed| {
+                if allowed.as_str().contains('/') {
+                    allowed.is_match(&name)
+                } else {
+                    allowed.is_match(&registry)
+                }
+            }) {
+                bail!(
+                    "image `{image_ref}` is not from an allowed registry (allowed: {})",
+                    self.allowed_registries
+                        .iter()
+                        .map(ImageGlob::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Transformer for ImageRegistryTransformer {
+    #[tracing::instrument(
+        skip_all,
+        name = "image_registry_transform",
+        fields(mirrors = self.mirrors.len(), allowed_registries = self.allowed_registries.len())
+    )]
+    async fn transform(&mut self, resources: &mut ResourceMap) -> anyhow::Result<()> {
+        let field_specs = &crate::fieldspec::Builtin::load().images;
+
+        for resource in resources.iter_mut() {
+            let id = resource.id().clone();
+            field_specs
+                .apply::<String>(resource, |image_ref| self.rewrite(image_ref))
+                .with_context(|| format!("rewriting images of `{id}`"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Pins images to the digests recorded in an image lock file.
+/// The lock file is a YAML (or JSON) mapping from image references (`repo:tag`) to digests.
+#[derive(Debug, Clone)]
+pub struct ImageLockTransformer {
+    path: PathId,
+    digests: IndexMap<Str, Str>,
+}
+
+impl ImageLockTransformer {
+    pub fn load(path: PathId) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("reading image lock `{}`", path.pretty()))?;
+        let entries = yaml::from_str::<IndexMap<Str, Str>>(&data)
+            .with_context(|| format!("parsing image lock `{}`", path.pretty()))?;
+
+        let mut digests = IndexMap::with_capacity(entries.len());
+        for (reference, digest) in entries {
+            let image = format!("{reference}@{digest}")
+                .parse::<ImageRef>()
+                .with_context(|| {
+                    format!(
+                        "invalid image lock entry `{reference}: {digest}` in `{}`",
+                        path.pretty()
+                    )
+                })?;
+            ensure!(
+                reference
+                    .parse::<ImageRef>()
+                    .is_ok_and(|r| r.digest.is_none()),
+                "invalid image lock entry `{reference}` in `{}`: keys must not have a digest",
+                path.pretty()
+            );
+            // Keys are normalized so that lookups only depend on the parsed reference.
+            let key = ImageRef {
+                digest: None,
+                ..image.clone()
+            };
+            digests.insert(key.to_string().into(), image.digest.unwrap());
+        }
+
+        Ok(Self { path, digests })
+    }
+
+    fn pin(&self, image_ref: &mut String) {
+        let Ok(mut image) = image_ref.parse::<ImageRef>() else {
+            return;
+        };
+        if image.digest.is_some() {
+            return;
+        }
+
+        if let Some(digest) = self.digests.get(image.to_string().as_str()) {
+            image.tag = None;
+            image.digest = Some(digest.clone());
+            *image_ref = image.to_string();
+        }
+    }
+
+    /// Fails if any image in `resources` isn't pinned to a digest.
+    pub fn ensure_pinned(resources: &ResourceMap) -> anyhow::Result<()> {
+        let unpinned = resources
+            .images()?
+            .into_iter()
+            .filter(|image| {
+                image
+                    .parse::<ImageRef>()
+                    .map_or(true, |image| image.digest.is_none())
+            })
+            .collect::<Vec<_>>();
+
+        if !unpinned.is_empty() {
+            bail!(
+                "images are not pinned to a digest by any image lock: {}",
+                unpinned.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Transformer for ImageLockTransformer {
+    #[tracing::instrument(
+        skip_all,
+        name = "image_lock_transform",
+        fields(path = %self.path.pretty())
+    )]
+    async fn transform(&mut self, resources: &mut ResourceMap) -> anyhow::Result<()> {
+        let field_specs = &crate::fieldspec::Builtin::load().images;
+
+        for resource in resources.iter_mut() {
+            let id = resource.id().clone();
+            field_specs
+                .apply::<String>(resource, |image_ref| {
+                    self.pin(image_ref);
+                    Ok(())
+                })
+                .with_context(|| format!("pinning images of `{id}`"))?;
+        }
+
+        Ok(())
+    }
+}
@@ -15,6 +15,7 @@ use super::Transformer;
 #[derive(Debug)]
 pub struct LabelTransformer<'a> {
     labels: Cow<'a, [Label]>,
+    unmatched_fields: Vec<(usize, usize)>,
 }
 
 impl fmt::Display for LabelTransformer<'_> {
@@ -49,8 +50,14 @@ impl<'a> LabelTransformer<'a> {
     pub fn new(labels: impl Into<Cow<'a, [Label]>>) -> Self {
         Self {
             labels: labels.into(),
+            unmatched_fields: Default::default(),
         }
     }
+
+    /// The `(label, field)` indices of the custom field specs that did not apply to any resource.
+    pub fn unmatched_fields(&self) -> &[(usize, usize)] {
+        &self.unmatched_fields
+    }
 }
 
 impl Transformer for LabelTransformer<'_> {
@@ -62,7 +69,7 @@ impl Transformer for LabelTransformer<'_> {
 
         let builtins = fieldspec::Builtin::load();
 
-        for label in &self.labels[..] {
+        for (i, label) in self.labels.iter().enumerate() {
             if label.pairs.is_empty() {
                 continue;
             }
@@ -73,21 +80,33 @@ impl Transformer for LabelTransformer<'_> {
                 (false, false) => &builtins.metadata_labels,
             };
 
-            let mut field_specs = Cow::Borrowed(field_specs);
+            let mut insert_labels = |l: &mut Object| {
+                for (key, value) in &label.pairs {
+                    l.insert(key.to_string(), json::Value::String(value.to_string()));
+                }
 
-            if !label.fields.is_empty() {
-                field_specs.to_mut().extend(label.fields.iter().cloned());
-            }
+                Ok(())
+            };
 
+            let mut matched = vec![false; label.fields.len()];
             for resource in resources.iter_mut() {
-                field_specs.apply::<Object>(resource, |l| {
-                    for (key, value) in &label.pairs {
-                        l.insert(key.to_string(), json::Value::String(value.to_string()));
-                    }
-
-                    Ok(())
-                })?;
+                field_specs.apply::<Object>(resource, &mut insert_labels)?;
+
+                for (j, spec) in label.fields.iter().enumerate() {
+                    spec.apply::<Object>(resource, &mut |l| {
+                        matched[j] = true;
+                        insert_labels(l)
+                    })?;
+                }
             }
+
+            self.unmatched_fields.extend(
+                matched
+                    .into_iter()
+                    .enumerate()
+                    .filter(|&(_, matched)| !matched)
+                    .map(|(j, _)| (i, j)),
+            );
         }
 
         Ok(())
@@ -1,34 +1,145 @@
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    fieldspec,
-    manifest::{Str, kind},
+    fieldspec::{self, FieldSpecs},
+    manifest::{Str, TypeMeta, apiversion, kind},
     patch::openapi,
     resmap::ResourceMap,
-    resource::Object,
+    resource::{Metadata, Object, Resource},
 };
 
-use super::Transformer;
+use super::{Rename, Transformer};
+
+/// Which `subjects` of (Cluster)RoleBindings have their namespace set, see kustomize's
+/// `setRoleBindingSubjects` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RoleBindingSubjects {
+    /// Subjects are left untouched.
+    None,
+    /// Only ServiceAccount subjects named `default` are updated.
+    #[default]
+    DefaultOnly,
+    /// All ServiceAccount subjects are updated.
+    AllServiceAccounts,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NamespaceTransformer {
+    #[serde(flatten)]
+    type_meta: TypeMeta<apiversion::Builtin, kind::NamespaceTransformer>,
+    metadata: Metadata,
+    /// Additional fields to set the namespace on, `metadata/namespace` and `subjects` are always
+    /// handled specially.
+    #[serde(default)]
+    field_specs: FieldSpecs,
+    /// Only set the namespace on resources (and fields) that do not already have one.
+    #[serde(default)]
+    unset_only: bool,
+    #[serde(default)]
+    set_role_binding_subjects: RoleBindingSubjects,
+}
+
+/// How a resource's identity changes under the transformation.
+enum Change {
+    Unchanged,
+    /// `Namespace` resources are renamed to the target namespace.
+    Name,
+    Namespace,
+}
 
-pub struct NamespaceTransformer(pub Str);
+impl NamespaceTransformer {
+    /// A transformer with the same behaviour as the `namespace` field of a kustomization.
+    pub fn new(namespace: Str) -> Self {
+        Self {
+            type_meta: TypeMeta::default(),
+            metadata: Metadata {
+                namespace: Some(namespace),
+                ..Default::default()
+            },
+            field_specs: Default::default(),
+            unset_only: false,
+            set_role_binding_subjects: RoleBindingSubjects::default(),
+        }
+    }
+
+    fn namespace(&self) -> anyhow::Result<&Str> {
+        match &self.metadata.namespace {
+            Some(namespace) if !namespace.is_empty() => Ok(namespace),
+            _ => bail!("NamespaceTransformer requires `metadata.namespace` to be set"),
+        }
+    }
+
+    /// The renames that applying this transformer to `resources` performs.
+    /// These must be recorded so references to the moved resources can be updated.
+    pub fn renames(&self, resources: &ResourceMap) -> anyhow::Result<Vec<Rename>> {
+        let spec = openapi::v2::Spec::load_global_default();
+        let namespace = self.namespace()?;
+
+        Ok(resources
+            .iter()
+            .filter_map(|res| match self.change(res, spec) {
+                Change::Unchanged => None,
+                Change::Name => Some(Rename::new_name(res.id().clone(), namespace.clone())),
+                Change::Namespace => {
+                    Some(Rename::new_namespace(res.id().clone(), namespace.clone()))
+                }
+            })
+            .collect())
+    }
+
+    fn change(&self, resource: &Resource, spec: &openapi::v2::Spec) -> Change {
+        let Some(namespace) = self.metadata.namespace.as_ref() else {
+            return Change::Unchanged;
+        };
+
+        if kind::Namespace == **resource.kind() {
+            // A `Namespace` always has a name, so there is nothing to fill in for `unsetOnly`.
+            if self.unset_only || resource.name() == namespace {
+                Change::Unchanged
+            } else {
+                Change::Name
+            }
+        } else if spec.is_namespaced(resource.gvk()) {
+            match resource.namespace() {
+                Some(ns) if self.unset_only && !ns.is_empty() => Change::Unchanged,
+                Some(ns) if ns == namespace => Change::Unchanged,
+                _ => Change::Namespace,
+            }
+        } else {
+            // Cluster-scoped resources remain unchanged
+            Change::Unchanged
+        }
+    }
+}
 
 impl Transformer for NamespaceTransformer {
-    #[tracing::instrument(skip_all, name = "namespace_transform", fields(namespace = %self.0))]
+    #[tracing::instrument(
+        skip_all,
+        name = "namespace_transform",
+        fields(namespace = ?self.metadata.namespace, unset_only = self.unset_only)
+    )]
     async fn transform(&mut self, resources: &mut ResourceMap) -> anyhow::Result<()> {
         let spec = openapi::v2::Spec::load_global_default();
         let builtin = &fieldspec::Builtin::load();
-        let target_namespace = self.0.to_string();
+        let target_namespace = self.namespace()?.clone();
 
         // A fresh map is allocated because a namespace change modifies the identity of the
         // resources, which can't be done in-place.
         let mut transformed_resources = ResourceMap::with_capacity(resources.len());
 
         for mut resource in std::mem::take(resources) {
-            // Apply defaultOnly subject transformation: only ServiceAccount subjects named "default"
-            // get their namespace updated. This matches kustomize's default behavior.
             // Other references are updated by the RenameTransformer when resources are renamed.
-            self.apply_default_subject_transformation(builtin, &mut resource, &target_namespace)?;
+            self.apply_subject_transformation(builtin, &mut resource, &target_namespace)?;
+            self.apply_field_specs(&mut resource, &target_namespace)?;
 
-            // Transform the resource itself based on its type
-            let transformed_resource = self.transform_resource(resource, spec)?;
+            let transformed_resource = match self.change(&resource, spec) {
+                Change::Unchanged => resource,
+                Change::Name => resource.with_name(target_namespace.clone()),
+                Change::Namespace => resource.with_namespace(Some(target_namespace.clone())),
+            };
             transformed_resources.insert(transformed_resource)?;
         }
 
@@ -38,15 +149,24 @@ impl Transformer for NamespaceTransformer {
 }
 
 impl NamespaceTransformer {
-    /// Apply namespace transformation to ServiceAccount subjects named "default"
-    fn apply_default_subject_transformation(
+    fn should_set(&self, current: Option<&str>) -> bool {
+        !self.unset_only || current.is_none_or(str::is_empty)
+    }
+
+    /// Apply the namespace to the ServiceAccount subjects selected by `setRoleBindingSubjects`.
+    fn apply_subject_transformation(
         &self,
         builtin: &fieldspec::Builtin,
-        resource: &mut crate::resource::Resource,
+        resource: &mut Resource,
         target_namespace: &str,
     ) -> anyhow::Result<()> {
+        if self.set_role_binding_subjects == RoleBindingSubjects::None {
+            return Ok(());
+        }
+
         builtin.subjects.apply::<Object>(resource, |subject| {
-            if self.is_default_service_account_subject(subject) {
+            let current = subject.get("namespace").and_then(|ns| ns.as_str());
+            if self.is_selected_subject(subject) && self.should_set(current) {
                 subject.insert(
                     "namespace".to_string(),
                     json::Value::String(target_namespace.to_string()),
@@ -56,29 +176,43 @@ impl NamespaceTransformer {
         })
     }
 
-    /// Check if a subject is a ServiceAccount named "default"
-    fn is_default_service_account_subject(&self, subject: &Object) -> bool {
+    fn is_selected_subject(&self, subject: &Object) -> bool {
         let kind = subject.get("kind").and_then(|k| k.as_str());
         let name = subject.get("name").and_then(|n| n.as_str());
 
-        matches!((kind, name), (Some(k), Some("default")) if kind::ServiceAccount == *k)
+        match self.set_role_binding_subjects {
+            RoleBindingSubjects::None => false,
+            RoleBindingSubjects::DefaultOnly => {
+                matches!((kind, name), (Some(k), Some("default")) if kind::ServiceAccount == *k)
+            }
+            RoleBindingSubjects::AllServiceAccounts => {
+                matches!(kind, Some(k) if kind::ServiceAccount == *k)
+            }
+        }
     }
 
-    /// Transform an individual resource based on its type
-    fn transform_resource(
+    /// Apply the namespace to any user-provided field specs.
+    fn apply_field_specs(
         &self,
-        resource: crate::resource::Resource,
-        spec: &openapi::v2::Spec,
-    ) -> anyhow::Result<crate::resource::Resource> {
-        if kind::Namespace == **resource.kind() {
-            // For Namespace resources, update the name, not the namespace
-            Ok(resource.with_name(self.0.clone()))
-        } else if spec.is_namespaced(resource.gvk()) {
-            // For namespaced resources, update their namespace
-            Ok(resource.with_namespace(Some(self.0.clone())))
-        } else {
-            // Cluster-scoped resources remain unchanged
-            Ok(resource)
+        resource: &mut Resource,
+        target_namespace: &str,
+    ) -> anyhow::Result<()> {
+        for field_spec in self.field_specs.iter() {
+            // `metadata/namespace` is handled by changing the resource's identity and subjects
+            // are handled by `setRoleBindingSubjects`.
+            let path = field_spec.path.to_string();
+            if path == "metadata/namespace" || path.starts_with("subjects") {
+                continue;
+            }
+
+            field_spec.apply::<String>(resource, &mut |namespace| {
+                if self.should_set(Some(namespace)) {
+                    *namespace = target_namespace.to_string();
+                }
+                Ok(())
+            })?;
         }
+
+        Ok(())
     }
 }
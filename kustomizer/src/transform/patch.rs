@@ -1,28 +1,38 @@
-use std::{collections::HashSet, fs::File, io::BufReader, sync::LazyLock};
+use std::sync::LazyLock;
 
-use anyhow::Context as _;
+use anyhow::{Context as _, ensure};
 use dashmap::DashMap;
 
 use crate::{
-    Located, PathExt, PathId,
-    manifest::{Manifest, Patch, Target},
+    LoadRestrictor, Located, PathExt, PathId,
+    manifest::{Manifest, Patch, PatchOptions, PatchType, Str, Target},
     resmap::ResourceMap,
-    resource::{GvkMatcher, Resource},
+    resource::{Document, GvkMatcher, ResId, Resource},
     yaml,
 };
 
-use super::Transformer;
+use super::{Rename, Transformer};
 use json_patch::Patch as JsonPatch;
 
-static PATCH_CACHE: LazyLock<DashMap<PathId, JsonPatch>> = LazyLock::new(Default::default);
+static PATCH_CACHE: LazyLock<DashMap<(PathId, Option<PatchType>), LoadedPatch>> =
+    LazyLock::new(Default::default);
+
+#[derive(Debug, Clone)]
+enum LoadedPatch {
+    StrategicMerge(Box<[Resource]>),
+    Json(JsonPatch),
+}
 
 pub struct PatchTransformer<'a, A, K> {
     manifest: &'a Located<Manifest<A, K>>,
     patches: &'a [Patch],
+    load_restrictor: LoadRestrictor,
+    renames: Vec<Rename>,
+    matched: Vec<bool>,
 }
 
 impl<'a, A, K> PatchTransformer<'a, A, K> {
-    pub fn new(manifest: &'a Located<Manifest<A, K>>) -> Self {
+    pub fn new(manifest: &'a Located<Manifest<A, K>>, load_restrictor: LoadRestrictor) -> Self {
         assert!(
             manifest.patches_strategic_merge.is_empty(),
             "patchesStrategicMerge should be translated to patches"
@@ -36,21 +46,107 @@ impl<'a, A, K> PatchTransformer<'a, A, K> {
         Self {
             patches: &manifest.patches,
             manifest,
+            load_restrictor,
+            renames: Default::default(),
+            matched: vec![false; manifest.patches.len()],
         }
     }
 
-    fn load_json_patch(&self, path: PathId) -> anyhow::Result<JsonPatch> {
-        match PATCH_CACHE.get(&path) {
-            Some(patch) => Ok(patch.clone()),
-            None => {
-                let file = File::open(path).with_context(|| {
-                    format!("opening JSON patch file at path `{}`", path.pretty())
-                })?;
-                let patch = yaml::from_reader::<JsonPatch>(BufReader::new(file))
-                    .with_context(|| format!("parsing JSON patch from file `{}`", path.pretty()))?;
-                Ok(patch)
+    /// The renames caused by patches that change the name or kind of resources, so that references
+    /// to the renamed resources can be updated.
+    pub fn take_renames(&mut self) -> Vec<Rename> {
+        std::mem::take(&mut self.renames)
+    }
+
+    /// The indices of the patches that did not apply to any resource.
+    pub fn unmatched(&self) -> impl Iterator<Item = usize> + '_ {
+        self.matched
+            .iter()
+            .enumerate()
+            .filter(|&(_, &matched)| !matched)
+            .map(|(i, _)| i)
+    }
+
+    fn load_patch(
+        &self,
+        path: PathId,
+        patch_type: Option<PatchType>,
+    ) -> anyhow::Result<LoadedPatch> {
+        if let Some(patch) = PATCH_CACHE.get(&(path, patch_type)) {
+            return Ok(patch.clone());
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("reading patch file at path `{}`", path.pretty()))?;
+
+        let patch = match patch_type.unwrap_or_else(|| PatchType::detect(&content)) {
+            PatchType::Json => yaml::from_str::<JsonPatch>(&content)
+                .map(LoadedPatch::Json)
+                .with_context(|| format!("parsing JSON patch from file `{}`", path.pretty()))?,
+            PatchType::StrategicMerge => yaml::from_reader_multi::<Document>(content.as_bytes())
+                .and_then(Resource::flatten_lists)
+                .map(LoadedPatch::StrategicMerge)
+                .with_context(|| {
+                    format!(
+                        "parsing strategic merge patches from file `{}`",
+                        path.pretty()
+                    )
+                })?,
+        };
+
+        PATCH_CACHE.insert((path, patch_type), patch.clone());
+        Ok(patch)
+    }
+
+    /// As in kustomize, a strategic merge patch without a target must not match several resources
+    /// by their current or previous ids.
+    fn ensure_unique_matches(&self, resources: &ResourceMap) -> anyhow::Result<()> {
+        for patch in self.patches {
+            let patches = match patch {
+                Patch::StrategicMerge {
+                    patch,
+                    target: None,
+                    ..
+                } => vec![patch.clone()],
+                Patch::OutOfLine {
+                    path,
+                    target: None,
+                    patch_type,
+                    ..
+                } => {
+                    let path = self
+                        .load_restrictor
+                        .resolve_file(&self.manifest.parent_path, path)?;
+                    match self.load_patch(path, *patch_type)? {
+                        LoadedPatch::StrategicMerge(patches) => patches.into_vec(),
+                        LoadedPatch::Json(_) => continue,
+                    }
+                }
+                _ => continue,
+            };
+
+            for patch in &patches {
+                let matches = resources
+                    .iter()
+                    .filter(|resource| {
+                        resource.any_id_matches(|id| {
+                            id.group == patch.id().group
+                                && id.version == patch.id().version
+                                && id.kind == patch.id().kind
+                                && id.name == patch.id().name
+                                && namespace_matches(&id, patch)
+                        })
+                    })
+                    .count();
+                ensure!(
+                    matches <= 1,
+                    "multiple resources match strategic merge patch `{}`, failed to find unique target for patch",
+                    patch.id()
+                );
             }
         }
+
+        Ok(())
     }
 
     fn apply_strategic_merge_patch(
@@ -58,56 +154,87 @@ impl<'a, A, K> PatchTransformer<'a, A, K> {
         resource: &mut Resource,
         patch: Resource,
         target: &Option<Target>,
+        options: PatchOptions,
     ) -> anyhow::Result<bool> {
-        let gvk = patch.gvk();
-        match target {
-            Some(target) => {
-                if !target.matches(resource) {
-                    return Ok(true);
-                }
-            }
-            None => {
-                // If no target is specified, match the patch gvk/name against the resource
-                let matcher = GvkMatcher {
-                    group: gvk.group.clone(),
-                    version: gvk.version.clone(),
-                    kind: gvk.kind.clone(),
-                };
-
-                if !resource.all_ids().any(|id| {
-                    let mut gvk = resource.gvk().clone();
-                    gvk.kind = id.kind.clone();
-                    matcher.matches(&gvk) && id.name == patch.name()
-                }) {
-                    return Ok(true);
-                }
-            }
+        // As in kustomize, the name and kind of a patch with a target are ignored unless it sets
+        // options, in which case a change of identity must be allowed by them. This is checked on
+        // the patch as merging drops its name.
+        let name = patch.name().clone();
+        let kind = patch.kind().clone();
+        if target.is_some() && (options.allow_name_change || options.allow_kind_change) {
+            ensure!(
+                name == *resource.name() || options.allow_name_change,
+                "patch changes the name of `{}` to `{name}`, set `options.allowNameChange: true` to allow this",
+                resource.id()
+            );
+            ensure!(
+                kind == *resource.kind() || options.allow_kind_change,
+                "patch changes the kind of `{}` to `{kind}`, set `options.allowKindChange: true` to allow this",
+                resource.id()
+            );
         }
 
-        resource.patch(patch)
+        if !resource.patch(patch)? {
+            return Ok(false);
+        }
+
+        if options.allow_name_change
+            && target.is_some()
+            && let Some(metadata) = resource
+                .root_mut()
+                .get_mut("metadata")
+                .and_then(|md| md.as_object_mut())
+        {
+            metadata.insert("name".into(), json::Value::String(name.to_string()));
+        }
+
+        if options.allow_kind_change && target.is_some() {
+            resource
+                .root_mut()
+                .insert("kind".into(), json::Value::String(kind.to_string()));
+        }
+
+        Ok(true)
     }
 }
 
 impl<A: Send + Sync, K: Send + Sync> Transformer for PatchTransformer<'_, A, K> {
     #[tracing::instrument(skip_all, name = "patch_transform")]
     async fn transform(&mut self, resources: &mut ResourceMap) -> anyhow::Result<()> {
-        let mut to_delete = HashSet::new();
+        // A fresh map is allocated because patches may modify the identity of the resources,
+        // which can't be done in-place.
+        self.ensure_unique_matches(resources)?;
+        let mut patched_resources = ResourceMap::with_capacity(resources.len());
 
-        for resource in resources.iter_mut() {
+        'resources: for mut resource in std::mem::take(resources) {
             let id = resource.id().clone();
-            for patch in self.patches {
+            for (i, patch) in self.patches.iter().enumerate() {
+                let options = patch.options();
                 match patch {
-                    Patch::Json { patch, target } => {
-                        if !target.matches(resource) {
+                    Patch::Json { patch, target, .. } => {
+                        if !target.matches(&resource) {
                             continue;
                         }
 
-                        json_patch(resource, patch)
+                        self.matched[i] = true;
+
+                        json_patch(&mut resource, patch)
                             .with_context(|| format!("applying JSON patch to resource `{id}`"))?;
+                        resource = sync_json_patch_identity(resource, options)?;
                     }
-                    Patch::StrategicMerge { patch, target } => {
+                    Patch::StrategicMerge { patch, target, .. } => {
+                        if !strategic_merge_patch_matches(&resource, patch, target) {
+                            continue;
+                        }
+
+                        self.matched[i] = true;
                         if !self
-                            .apply_strategic_merge_patch(resource, patch.clone(), target)
+                            .apply_strategic_merge_patch(
+                                &mut resource,
+                                patch.clone(),
+                                target,
+                                options,
+                            )
                             .with_context(|| {
                                 format!(
                                     "applying strategic merge patch to resource `{}`",
@@ -115,55 +242,165 @@ impl<A: Send + Sync, K: Send + Sync> Transformer for PatchTransformer<'_, A, K>
                                 )
                             })?
                         {
-                            assert!(to_delete.insert(id.clone()));
+                            continue 'resources;
                         }
+                        resource = sync_identity(resource, options)?;
                     }
-                    Patch::OutOfLine { path, target } => {
-                        let path = PathId::make(self.manifest.parent_path.join(path))?;
-                        let patches =
-                            Resource::load_many(path).context("loading out-of-line patches");
-
-                        if let Ok(patches) = patches {
-                            for patch in patches {
-                                if !self.apply_strategic_merge_patch(resource, patch, target)
-                                    .with_context(|| {
-                                        format!(
-                                            "applying strategic merge patch from `{}` to resource `{}`",
-                                            path.pretty(),
-                                            resource.id()
-                                        )
-                                    })? {
-                                        assert!(to_delete.insert(id.clone()));
+                    Patch::OutOfLine {
+                        path,
+                        target,
+                        patch_type,
+                        ..
+                    } => {
+                        let path = self
+                            .load_restrictor
+                            .resolve_file(&self.manifest.parent_path, path)?;
+                        match self.load_patch(path, *patch_type)? {
+                            LoadedPatch::StrategicMerge(patches) => {
+                                for patch in patches {
+                                    if !strategic_merge_patch_matches(&resource, &patch, target) {
+                                        continue;
                                     }
+
+                                    self.matched[i] = true;
+                                    if !self.apply_strategic_merge_patch(&mut resource, patch, target, options)
+                                        .with_context(|| {
+                                            format!(
+                                                "applying strategic merge patch from `{}` to resource `{}`",
+                                                path.pretty(),
+                                                resource.id()
+                                            )
+                                        })? {
+                                            continue 'resources;
+                                        }
+                                    resource = sync_identity(resource, options)?;
+                                }
                             }
-                        } else {
-                            let patch = self.load_json_patch(path)?;
+                            LoadedPatch::Json(patch) => {
+                                let target = target.as_ref().ok_or_else(|| {
+                                    anyhow::anyhow!(
+                                        "patch target is required for json patch at `{}`",
+                                        path.pretty()
+                                    )
+                                })?;
 
-                            let target = target.as_ref().ok_or_else(|| {
-                                anyhow::anyhow!(
-                                    "patch target is required for json patch at `{}`",
-                                    path.pretty()
-                                )
-                            })?;
+                                if !target.matches(&resource) {
+                                    continue;
+                                }
 
-                            if !target.matches(resource) {
-                                continue;
+                                self.matched[i] = true;
+                                json_patch(&mut resource, &patch)?;
+                                resource = sync_json_patch_identity(resource, options)?;
                             }
-
-                            json_patch(resource, &patch)?;
                         }
                     }
                 }
             }
+
+            if *resource.name() != id.name || *resource.kind() != id.kind {
+                let mut rename = Rename::new_name(id.clone(), resource.name().clone());
+                if *resource.kind() != id.kind {
+                    rename = rename.with_kind(resource.kind().clone());
+                }
+                self.renames.push(rename);
+            }
+
+            patched_resources.insert(resource)?;
         }
 
-        resources.retain(|id, _| !to_delete.contains(id));
+        *resources = patched_resources;
 
         Ok(())
     }
 }
 
+/// Whether a strategic merge patch applies to the resource, either by its target or, if it has
+/// none, by the patch's own gvk and name.
+fn strategic_merge_patch_matches(
+    resource: &Resource,
+    patch: &Resource,
+    target: &Option<Target>,
+) -> bool {
+    match target {
+        Some(target) => target.matches(resource),
+        None => {
+            let gvk = patch.gvk();
+            let matcher = GvkMatcher {
+                group: gvk.group.clone(),
+                version: gvk.version.clone(),
+                kind: gvk.kind.clone(),
+            };
+
+            resource.all_ids().any(|id| {
+                let mut gvk = resource.gvk().clone();
+                gvk.kind = id.kind.clone();
+                matcher.matches(&gvk) && id.name == patch.name() && namespace_matches(&id, patch)
+            })
+        }
+    }
+}
+
+/// A patch without a namespace applies to resources in any namespace.
+fn namespace_matches(id: &ResId, patch: &Resource) -> bool {
+    patch.namespace().is_none() || id.namespace.as_ref() == patch.namespace()
+}
+
+/// JSON patches that set either option update the identity of the resource to match the body,
+/// failing if they change the part of it that isn't allowed. Other JSON patches only change the
+/// body, so that later patches and merges still refer to the resource by its original id.
+fn sync_json_patch_identity(
+    mut resource: Resource,
+    options: PatchOptions,
+) -> anyhow::Result<Resource> {
+    if options.allow_name_change || options.allow_kind_change {
+        return sync_identity(resource, options);
+    }
+
+    resource.root_mut().shift_remove("kind");
+    Ok(resource)
+}
+
+/// Updates the identity of the resource to match its body after a patch has been applied,
+/// returning an error if the patch changed the name or kind without being allowed to.
+/// The kind is not part of the body, so patches that may change it leave it under the `kind` key.
+fn sync_identity(mut resource: Resource, options: PatchOptions) -> anyhow::Result<Resource> {
+    let kind = resource
+        .root_mut()
+        .shift_remove("kind")
+        .and_then(|kind| kind.as_str().map(Str::from));
+    let name = resource
+        .root()
+        .get("metadata")
+        .and_then(|md| md.get("name"))
+        .and_then(|name| name.as_str())
+        .map(Str::from);
+
+    if let Some(name) = name.filter(|name| name != resource.name()) {
+        ensure!(
+            options.allow_name_change,
+            "patch changes the name of `{}` to `{name}`, set `options.allowNameChange: true` to allow this",
+            resource.id()
+        );
+        resource = resource.with_name(name);
+    }
+
+    if let Some(kind) = kind.filter(|kind| kind != resource.kind()) {
+        ensure!(
+            options.allow_kind_change,
+            "patch changes the kind of `{}` to `{kind}`, set `options.allowKindChange: true` to allow this",
+            resource.id()
+        );
+        resource = resource.with_kind(kind);
+    }
+
+    Ok(resource)
+}
+
 fn json_patch(resource: &mut Resource, patch: &JsonPatch) -> anyhow::Result<()> {
+    let kind = resource.kind().to_string();
+    resource
+        .root_mut()
+        .insert("kind".into(), json::Value::String(kind));
     json_patch::patch(resource.root_raw_mut(), patch)
         .with_context(|| format!("applying JSON patch to resource `{}`", resource.id()))
 }
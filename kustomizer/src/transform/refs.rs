@@ -2,6 +2,7 @@ use anyhow::anyhow;
 use json::Value;
 
 use crate::{
+    fieldspec::FieldSpec,
     manifest::Str,
     resmap::ResourceMap,
     resource::{RefSpecs, ResId},
@@ -18,6 +19,8 @@ pub struct Rename {
     new_name: Str,
     /// The new namespace for the resource (None means remove namespace)
     new_namespace: Option<Str>,
+    /// The new kind for the resource, if it changed
+    new_kind: Option<Str>,
 }
 
 impl Rename {
@@ -28,9 +31,16 @@ impl Rename {
             res_id,
             new_name,
             new_namespace,
+            new_kind: None,
         }
     }
 
+    /// Also change the kind, which is updated in references that specify a kind
+    pub fn with_kind(mut self, new_kind: Str) -> Self {
+        self.new_kind = Some(new_kind);
+        self
+    }
+
     /// Create a rename operation that only changes the name
     pub fn new_name(res_id: ResId, new_name: Str) -> Self {
         let namespace = res_id.namespace.clone();
@@ -80,6 +90,10 @@ impl RenameTransformer<'_> {
     ) -> anyhow::Result<()> {
         // For every referrer that can refer to this resource type
         for referrer_spec in self.ref_specs.referrers(&rename.res_id.gvk) {
+            if let Some(new_kind) = &rename.new_kind {
+                self.apply_kind_change(referrer_spec, &rename.res_id, new_kind, resources)?;
+            }
+
             // Update references in every resource
             for resource in resources.iter_mut() {
                 referrer_spec.apply::<Value>(resource, &mut |reference_value| {
@@ -90,6 +104,40 @@ impl RenameTransformer<'_> {
         Ok(())
     }
 
+    /// References to a resource by name such as `roleRef/name` may specify the kind beside the
+    /// name, which must follow the kind of the resource.
+    fn apply_kind_change(
+        &self,
+        referrer_spec: &FieldSpec,
+        res_id: &ResId,
+        new_kind: &Str,
+        resources: &mut ResourceMap,
+    ) -> anyhow::Result<()> {
+        let Some(parent) = referrer_spec.path.parent() else {
+            return Ok(());
+        };
+
+        let spec = FieldSpec {
+            path: parent,
+            ..referrer_spec.clone()
+        };
+
+        for resource in resources.iter_mut() {
+            spec.apply::<Value>(resource, &mut |value| {
+                if let Value::Object(ref_obj) = value
+                    && ref_obj.contains_key("kind")
+                    && ref_obj.contains_key("name")
+                    && reference_matches(ref_obj, res_id)?
+                {
+                    ref_obj.insert("kind".to_string(), Value::String(new_kind.to_string()));
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+
     /// Update a reference value if it matches the resource being renamed
     fn update_reference_if_matches(
         &self,
@@ -105,7 +153,7 @@ impl RenameTransformer<'_> {
             }
             Value::Object(ref_obj) => {
                 // Complex reference with name, kind, and possibly namespace
-                if self.reference_matches_resource(ref_obj, &rename.res_id)? {
+                if reference_matches(ref_obj, &rename.res_id)? {
                     self.update_reference_fields(ref_obj, rename);
                 }
             }
@@ -118,28 +166,6 @@ impl RenameTransformer<'_> {
         Ok(())
     }
 
-    /// Check if a reference object matches the resource being renamed
-    fn reference_matches_resource(
-        &self,
-        ref_obj: &json::Map<String, Value>,
-        res_id: &ResId,
-    ) -> anyhow::Result<bool> {
-        let name = ref_obj
-            .get("name")
-            .ok_or_else(|| anyhow!("Reference object missing 'name' field"))?
-            .as_str()
-            .ok_or_else(|| anyhow!("Reference 'name' field is not a string"))?;
-
-        let kind = ref_obj.get("kind").and_then(|k| k.as_str());
-        let namespace = ref_obj.get("namespace").and_then(|n| n.as_str());
-
-        Ok(name == res_id.name
-            && kind.is_none_or(|k| k == res_id.gvk.kind)
-            && (namespace.is_none()
-                || namespace == res_id.namespace.as_deref()
-                || res_id.namespace.is_none() && namespace == Some("default")))
-    }
-
     /// Update the name and namespace fields in a reference object
     fn update_reference_fields(
         &self,
@@ -152,6 +178,12 @@ impl RenameTransformer<'_> {
             Value::String(rename.new_name.to_string()),
         );
 
+        if let Some(new_kind) = &rename.new_kind
+            && reference_map.contains_key("kind")
+        {
+            reference_map.insert("kind".to_string(), Value::String(new_kind.to_string()));
+        }
+
         // Update or remove the namespace
         match &rename.new_namespace {
             Some(new_namespace) => {
@@ -166,3 +198,24 @@ impl RenameTransformer<'_> {
         }
     }
 }
+
+/// Check if a reference object, with a name and optionally a kind and namespace, refers to the resource
+pub(super) fn reference_matches(
+    ref_obj: &json::Map<String, Value>,
+    res_id: &ResId,
+) -> anyhow::Result<bool> {
+    let name = ref_obj
+        .get("name")
+        .ok_or_else(|| anyhow!("Reference object missing 'name' field"))?
+        .as_str()
+        .ok_or_else(|| anyhow!("Reference 'name' field is not a string"))?;
+
+    let kind = ref_obj.get("kind").and_then(|k| k.as_str());
+    let namespace = ref_obj.get("namespace").and_then(|n| n.as_str());
+
+    Ok(name == res_id.name
+        && kind.is_none_or(|k| k == res_id.gvk.kind)
+        && (namespace.is_none()
+            || namespace == res_id.namespace.as_deref()
+            || res_id.namespace.is_none() && namespace == Some("default")))
+}
@@ -1,37 +1,40 @@
-use std::collections::HashMap;
-
-use crate::{
-    fieldspec,
-    manifest::{Replica, Str},
-    resmap::ResourceMap,
-};
+use crate::{fieldspec, manifest::Replica, resmap::ResourceMap};
 
 use super::Transformer;
 
-pub struct ReplicaTransformer {
-    replicas: HashMap<Str, u32>,
+pub struct ReplicaTransformer<'a> {
+    replicas: &'a [Replica],
+    matched: Vec<bool>,
 }
 
-impl ReplicaTransformer {
-    pub fn new(replicas: &[Replica]) -> Self {
+impl<'a> ReplicaTransformer<'a> {
+    pub fn new(replicas: &'a [Replica]) -> Self {
         Self {
-            replicas: replicas
-                .iter()
-                .map(|replica| (replica.name.clone(), replica.count))
-                .collect(),
+            replicas,
+            matched: vec![false; replicas.len()],
         }
     }
+
+    /// The indices of the replica entries that did not match any resource.
+    pub fn unmatched(&self) -> impl Iterator<Item = usize> + '_ {
+        self.matched
+            .iter()
+            .enumerate()
+            .filter(|&(_, &matched)| !matched)
+            .map(|(i, _)| i)
+    }
 }
 
-impl Transformer for ReplicaTransformer {
+impl Transformer for ReplicaTransformer<'_> {
     #[tracing::instrument(skip_all, name = "replica_transform")]
     async fn transform(&mut self, resources: &mut ResourceMap) -> anyhow::Result<()> {
         let field_specs = &fieldspec::Builtin::load().replicas;
-        for (name, replicas) in &self.replicas {
+        for (i, replica) in self.replicas.iter().enumerate() {
             for resource in resources.iter_mut() {
-                if resource.any_id_matches(|id| id.name == name) {
+                if resource.any_id_matches(|id| id.name == replica.name) {
                     field_specs.apply::<u64>(resource, |replicas_field| {
-                        *replicas_field = *replicas as u64;
+                        self.matched[i] = true;
+                        *replicas_field = replica.count as u64;
                         Ok(())
                     })?;
                 }
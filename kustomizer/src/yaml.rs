@@ -93,3 +93,10 @@ pub fn to_fmt_writer<W: std::fmt::Write, T: serde::Serialize>(
 ) -> anyhow::Result<()> {
     serde_saphyr::to_fmt_writer_with_options(output, value, OPTS).map_err(Into::into)
 }
+
+pub fn to_io_writer<W: std::io::Write, T: serde::Serialize>(
+    output: &mut W,
+    value: &T,
+) -> anyhow::Result<()> {
+    serde_saphyr::to_io_writer_with_options(output, value, OPTS).map_err(Into::into)
+}
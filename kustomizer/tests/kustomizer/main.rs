@@ -1,5 +1,5 @@
 use kustomizer::{
-    PathExt,
+    BuildOptions, ExecOptions, PathExt,
     dbg::{diff_reference_impl, format_chunks},
     manifest::Str,
     yaml,
@@ -20,6 +20,20 @@ struct TestManifest {
     name: Str,
     #[serde(default)]
     kind: TestKind,
+    /// Whether to compare against the reference kustomize implementation.
+    /// Disabled for tests of behaviour that kustomize does not support.
+    #[serde(default = "default_reference")]
+    reference: bool,
+    /// Whether to build with `--enable-exec`.
+    #[serde(default)]
+    enable_exec: bool,
+    /// Whether to build with `--decrypt`, overriding the `decrypt` field of kustomizations.
+    #[serde(default)]
+    decrypt: Option<bool>,
+}
+
+fn default_reference() -> bool {
+    true
 }
 
 #[derive(Debug, Default, serde::Deserialize)]
@@ -30,6 +44,9 @@ enum TestKind {
     Fail,
 }
 
+/// The age key of the SOPS-encrypted resources in the testdata.
+const SOPS_AGE_KEY: &str = include_str!("testdata/sops-decrypt/keys.txt");
+
 #[tokio::main]
 async fn test(path: &Path) -> datatest_stable::Result<()> {
     let base_path = path.parent().unwrap();
@@ -37,9 +54,24 @@ async fn test(path: &Path) -> datatest_stable::Result<()> {
     let error_snapshot_path = base_path.join("error").with_extension("stderr");
 
     let data = std::fs::read_to_string(path).context("reading test manifest")?;
-    let TestManifest { name: _, kind } = yaml::from_str(&data).context("parsing test manifest")?;
-
-    let res = kustomizer::build(base_path).await;
+    let TestManifest {
+        name: _,
+        kind,
+        reference,
+        enable_exec,
+        decrypt,
+    } = yaml::from_str(&data).context("parsing test manifest")?;
+
+    let options = BuildOptions {
+        exec: ExecOptions {
+            enabled: enable_exec,
+            ..Default::default()
+        },
+        decrypt,
+        sops_age_keys: Some(SOPS_AGE_KEY.into()),
+        ..Default::default()
+    };
+    let res = kustomizer::build_with_options(base_path, options).await;
 
     match (res, kind) {
         (Ok(resmap), TestKind::Success) => {
@@ -58,11 +90,17 @@ async fn test(path: &Path) -> datatest_stable::Result<()> {
             }
             res?;
 
-            diff_reference_impl(base_path, &actual)?;
+            if reference {
+                diff_reference_impl(base_path, &actual)?;
+            }
         }
         (Err(err), TestKind::Fail) => {
-            let stderr = reference_impl_error(base_path)
-                .with_context(|| format!("kustomizer error {err:?} at {}", path.pretty()))?;
+            let stderr = if reference {
+                reference_impl_error(base_path)
+                    .with_context(|| format!("kustomizer error {err:?} at {}", path.pretty()))?
+            } else {
+                String::new()
+            };
 
             let res = snapshot(
                 &error_snapshot_path,